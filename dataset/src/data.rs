@@ -1,31 +1,50 @@
 use crate::{
+    generate::DataSource,
     point::{Point, SpiralItem},
     workers,
 };
 #[cfg(not(target_family = "wasm"))]
 use burn::data::{dataloader::batcher::Batcher, dataset::Dataset};
-use burn::tensor::{Tensor, backend::Backend};
+use burn::tensor::{Int, Tensor, backend::Backend};
 use flume::Receiver;
 
 #[derive(Debug, Clone)]
 pub struct SpiralDataset {
     pub epoch_size: usize,
+    label_fraction: f64,
     ch: Receiver<SpiralItem>,
 }
 
 impl SpiralDataset {
     pub fn new(epoch_size: usize) -> Self {
+        Self::with_source(epoch_size, DataSource::SwissRoll)
+    }
+
+    pub fn with_source(epoch_size: usize, source: DataSource) -> Self {
         Self {
             epoch_size,
-            ch: workers::init(),
+            label_fraction: 1.,
+            ch: workers::init(source),
         }
     }
+
+    pub fn with_label_fraction(mut self, label_fraction: f64) -> Self {
+        self.label_fraction = label_fraction;
+        self
+    }
 }
 
 #[cfg(not(target_family = "wasm"))]
 impl Dataset<SpiralItem> for SpiralDataset {
     fn get(&self, _idx: usize) -> Option<SpiralItem> {
-        self.ch.recv().ok()
+        self.ch.recv().ok().map(|mut item| {
+            item.labeled = rand::random::<f64>() < self.label_fraction;
+            if !item.labeled {
+                item.label = 0.;
+                item.class = 0;
+            }
+            item
+        })
     }
 
     fn len(&self) -> usize {
@@ -48,6 +67,24 @@ impl<B: Backend> SpiralBatcher<B> {
 pub struct SpiralBatch<B: Backend> {
     pub points: Tensor<B, 3>,
     pub labels: Tensor<B, 2>,
+    pub classes: Tensor<B, 2, Int>,
+    pub label_mask: Tensor<B, 2>,
+}
+
+impl<B: Backend> SpiralBatch<B> {
+    pub fn one_hot(&self, n_classes: usize) -> Tensor<B, 2> {
+        let batchsize = self.classes.dims()[0];
+        let classes = Tensor::<B, 1, Int>::arange(0..n_classes)
+            .to_device(&self.classes.device())
+            .reshape([1, n_classes])
+            .repeat(0, batchsize);
+
+        self.classes
+            .clone()
+            .repeat(1, n_classes)
+            .equal(classes)
+            .float()
+    }
 }
 
 #[cfg(not(target_family = "wasm"))]
@@ -71,18 +108,70 @@ impl<B: Backend> Batcher<SpiralItem, SpiralBatch<B>> for SpiralBatcher<B> {
                 .to_device(&self.device);
         let labels = Tensor::cat(batch.map(|(_, l)| l).collect(), 0)
             .to_device(&self.device);
+        let classes = Tensor::cat(
+            items
+                .iter()
+                .map(|item| {
+                    Tensor::<B, 1, Int>::from_ints([item.class as i32])
+                        .reshape([1, 1])
+                })
+                .collect(),
+            0,
+        )
+        .to_device(&self.device);
+        let label_mask = Tensor::cat(
+            items
+                .iter()
+                .map(|item| {
+                    Tensor::<B, 1>::from_floats(
+                        [item.labeled as u8 as f32],
+                    )
+                    .reshape([1, 1])
+                })
+                .collect(),
+            0,
+        )
+        .to_device(&self.device);
 
-        SpiralBatch { points, labels }
+        SpiralBatch {
+            points,
+            labels,
+            classes,
+            label_mask,
+        }
     }
 }
 
 #[cfg(not(target_family = "wasm"))]
 pub fn get_data(n: usize) -> (Vec<Point>, Vec<f32>) {
-    let dataset = SpiralDataset::new(n);
-    (0..n)
-        .map(|i| dataset.get(i).unwrap())
-        .map(|item| (item.point, item.label))
-        .unzip()
+    get_data_from(n, DataSource::SwissRoll)
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn get_data_from(
+    n: usize,
+    source: DataSource,
+) -> (Vec<Point>, Vec<f32>) {
+    let (points, labels, _) = get_classified_data_from(n, source);
+    (points, labels)
+}
+
+/// Points, labels and classes of `n` items from `source`.
+#[cfg(not(target_family = "wasm"))]
+pub fn get_classified_data_from(
+    n: usize,
+    source: DataSource,
+) -> (Vec<Point>, Vec<f32>, Vec<usize>) {
+    let dataset = SpiralDataset::with_source(n, source);
+    let items = (0..n).map(|i| dataset.get(i).unwrap());
+    let (mut points, mut labels, mut classes) =
+        (Vec::new(), Vec::new(), Vec::new());
+    items.for_each(|item| {
+        points.push(item.point);
+        labels.push(item.label);
+        classes.push(item.class);
+    });
+    (points, labels, classes)
 }
 
 #[cfg(target_family = "wasm")]
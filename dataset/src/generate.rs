@@ -1,7 +1,72 @@
 use crate::point::{Point, SpiralItem};
+use burn::config::Config;
 use flume::Sender;
+use std::f32::consts::PI;
 
-pub fn generate_data(n_samples: u32, tx: Sender<SpiralItem>) {
+/// Label range and height of the roll, matching sklearn's swiss roll.
+pub const T_MIN: f32 = 1.5 * PI;
+pub const T_MAX: f32 = 4.5 * PI;
+pub const HEIGHT: f32 = 21.;
+
+#[derive(Config, Debug, PartialEq, Eq, Hash)]
+pub enum DataSource {
+    SwissRoll,
+    Spirals(usize),
+}
+
+impl DataSource {
+    pub fn n_classes(&self) -> usize {
+        match self {
+            Self::SwissRoll => 1,
+            Self::Spirals(n_arms) => *n_arms,
+        }
+    }
+
+    /// Distance from `point` to the set of points generated at label `t`,
+    /// a vertical segment per arm.
+    pub fn slice_distance(&self, point: &Point, t: f32) -> f32 {
+        (0..self.n_classes().max(1))
+            .map(|arm| self.arm_slice_distance(point, t, arm))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    /// Distance from `point` to arm `arm` over the whole label range,
+    /// searched on a fine grid of labels.
+    pub fn arm_distance(&self, point: &Point, arm: usize) -> f32 {
+        const STEPS: usize = 1024;
+        (0..=STEPS)
+            .map(|i| T_MIN + (T_MAX - T_MIN) * i as f32 / STEPS as f32)
+            .map(|t| self.arm_slice_distance(point, t, arm))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    /// Distance from `point` to the segment of arm `arm` at label `t`.
+    pub fn arm_slice_distance(
+        &self,
+        point: &Point,
+        t: f32,
+        arm: usize,
+    ) -> f32 {
+        let phase = 2. * PI * arm as f32 / self.n_classes().max(1) as f32;
+        let dx = point[0] - t * (t + phase).cos();
+        let dy = (-point[1]).max(point[1] - HEIGHT).max(0.);
+        let dz = point[2] - t * (t + phase).sin();
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+}
+
+pub fn generate_data(
+    source: DataSource,
+    n_samples: u32,
+    tx: Sender<SpiralItem>,
+) {
+    match source {
+        DataSource::SwissRoll => swiss_roll(n_samples, tx),
+        DataSource::Spirals(n_arms) => spirals(n_arms, tx),
+    }
+}
+
+fn swiss_roll(n_samples: u32, tx: Sender<SpiralItem>) {
     #[cfg(not(target_family = "wasm"))]
     {
         use pyo3::{prelude::*, types::IntoPyDict};
@@ -22,6 +87,8 @@ pub fn generate_data(n_samples: u32, tx: Sender<SpiralItem>) {
                     tx.send(SpiralItem {
                         point: pt,
                         label: t,
+                        class: 0,
+                        labeled: true,
                     })
                     .expect("to send item to channel");
                 });
@@ -29,3 +96,30 @@ pub fn generate_data(n_samples: u32, tx: Sender<SpiralItem>) {
         })
     }
 }
+
+fn spirals(n_arms: usize, tx: Sender<SpiralItem>) {
+    #[cfg(not(target_family = "wasm"))]
+    {
+        use rand::Rng;
+
+        let n_arms = n_arms.max(1);
+        let mut rng = rand::thread_rng();
+        loop {
+            let class = rng.gen_range(0..n_arms);
+            let t = rng.gen_range(T_MIN..T_MAX);
+            let phase = 2. * PI * class as f32 / n_arms as f32;
+
+            tx.send(SpiralItem {
+                point: [
+                    t * (t + phase).cos(),
+                    rng.gen_range(0. ..HEIGHT),
+                    t * (t + phase).sin(),
+                ],
+                label: t,
+                class,
+                labeled: true,
+            })
+            .expect("to send item to channel");
+        }
+    }
+}
@@ -4,4 +4,5 @@ mod point;
 mod workers;
 
 pub use data::*;
+pub use generate::{DataSource, HEIGHT, T_MAX, T_MIN};
 pub use point::*;
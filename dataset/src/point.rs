@@ -10,6 +10,8 @@ pub type Point = [f32; INPUT_DIM];
 pub struct SpiralItem {
     pub point: Point,
     pub label: f32,
+    pub class: usize,
+    pub labeled: bool,
 }
 
 #[cfg(not(target_family = "wasm"))]
@@ -1,21 +1,31 @@
-use crate::{generate::generate_data, point::SpiralItem};
-use flume::{Receiver, Sender, bounded};
+use crate::{
+    generate::{DataSource, generate_data},
+    point::SpiralItem,
+};
+use flume::{Receiver, bounded};
 use once_cell::sync::OnceCell;
+use std::{collections::HashMap, sync::Mutex};
 
-static CHAN: OnceCell<(Sender<SpiralItem>, Receiver<SpiralItem>)> =
-    OnceCell::new();
-static T: OnceCell<[std::thread::JoinHandle<()>; 2]> = OnceCell::new();
+type Channels = HashMap<DataSource, Receiver<SpiralItem>>;
 
-pub fn init() -> Receiver<SpiralItem> {
-    let (tx, rx) = CHAN.get_or_init(|| bounded(1_000_000));
-    T.get_or_init(|| {
-        std::array::from_fn(|_| {
-            let tx = tx.clone();
-            std::thread::spawn(move || {
-                generate_data(1000, tx);
-            })
-        })
-    });
+static CHANNELS: OnceCell<Mutex<Channels>> = OnceCell::new();
+
+pub fn init(source: DataSource) -> Receiver<SpiralItem> {
+    CHANNELS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("worker channels to be available")
+        .entry(source.clone())
+        .or_insert_with(|| {
+            let (tx, rx) = bounded(1_000_000);
+            (0..2).for_each(|_| {
+                let (tx, source) = (tx.clone(), source.clone());
+                std::thread::spawn(move || {
+                    generate_data(source, 1000, tx);
+                });
+            });
 
-    rx.clone()
+            rx
+        })
+        .clone()
 }
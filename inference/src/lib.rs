@@ -1,12 +1,9 @@
-use burn::{
-    record::{BinBytesRecorder, FullPrecisionSettings, Recorder},
-    tensor::Device,
-};
+use burn::tensor::Device;
 pub use dataset::Point;
 use once_cell::sync::OnceCell;
 pub use train::TrainingConfig as ModelConfig;
-pub use train::load_model;
-use vae::Model as M;
+pub use train::{Weights, load_generator, load_model};
+use vae::Generator;
 
 #[cfg(not(target_family = "wasm"))]
 use burn::backend::{
@@ -19,7 +16,7 @@ type Backend = Fusion<Wgpu<AutoGraphicsApi, f32, i32>>;
 #[cfg(target_family = "wasm")]
 type Backend = NdArray<f32>;
 
-pub type Model = M<Backend>;
+pub type Model = Generator<Backend>;
 
 static DEVICE: OnceCell<Device<Backend>> = OnceCell::new();
 static MODEL: OnceCell<Model> = OnceCell::new();
@@ -28,6 +25,10 @@ static MODEL: OnceCell<Model> = OnceCell::new();
 use burn::backend::{NdArray, ndarray::NdArrayDevice};
 
 pub fn init(dir: &str) {
+    init_with(dir, Weights::Trained);
+}
+
+pub fn init_with(dir: &str, weights: Weights) {
     #[cfg(not(target_family = "wasm"))]
     let device = WgpuDevice::BestAvailable;
     #[cfg(target_family = "wasm")]
@@ -35,20 +36,34 @@ pub fn init(dir: &str) {
 
     let device = DEVICE.get_or_init(|| device);
     MODEL
-        .set(train::load_model::<Backend>(dir, device))
+        .set(train::load_generator_with::<Backend>(dir, weights, device))
         .expect("Failed to initialize model");
 }
 
 pub fn load_bytes(config: ModelConfig, weights: Vec<u8>) {
     #[cfg(not(target_family = "wasm"))]
-    DEVICE.get_or_init(|| WgpuDevice::BestAvailable);
+    let device = DEVICE.get_or_init(|| WgpuDevice::BestAvailable);
     #[cfg(target_family = "wasm")]
-    DEVICE.get_or_init(NdArrayDevice::default);
+    let device = DEVICE.get_or_init(NdArrayDevice::default);
 
-    let record = BinBytesRecorder::<FullPrecisionSettings>::default()
-        .load(weights)
-        .expect("Failed to load weights");
-    MODEL.get_or_init(|| config.model.init_with::<Backend>(record));
+    MODEL.get_or_init(|| {
+        let precision = &config.export_precision;
+        train::generator_from_bytes(
+            &config, weights, precision, precision, device,
+        )
+    });
+}
+
+/// Loads int8 weights written with `quantize`, dequantizing them to f32.
+pub fn load_quantized(config: ModelConfig, weights: Vec<u8>) {
+    #[cfg(not(target_family = "wasm"))]
+    let device = DEVICE.get_or_init(|| WgpuDevice::BestAvailable);
+    #[cfg(target_family = "wasm")]
+    let device = DEVICE.get_or_init(NdArrayDevice::default);
+
+    MODEL.get_or_init(|| {
+        train::generator_from_quantized(&config, &weights, device)
+    });
 }
 
 #[cfg(not(target_family = "wasm"))]
@@ -70,6 +85,227 @@ pub async fn generate(t: f32, n: usize) -> Vec<Point> {
         .await
 }
 
+#[cfg(not(target_family = "wasm"))]
+pub fn generate_guided(
+    t: f32,
+    n: usize,
+    guidance_scale: f64,
+) -> Vec<Point> {
+    let device = DEVICE.get().expect("Call .init() to load model");
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .generate_guided(t, n, guidance_scale, device)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn generate_guided(
+    t: f32,
+    n: usize,
+    guidance_scale: f64,
+) -> Vec<Point> {
+    let device = DEVICE.get().expect("Call .init() to load model");
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .generate_guided(t, n, guidance_scale, device)
+        .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn generate_class(class: usize, n: usize) -> Vec<Point> {
+    let device = DEVICE.get().expect("Call .init() to load model");
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .generate_class(class, n, device)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn generate_class(class: usize, n: usize) -> Vec<Point> {
+    let device = DEVICE.get().expect("Call .init() to load model");
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .generate_class(class, n, device)
+        .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn generate_class_guided(
+    class: usize,
+    n: usize,
+    guidance_scale: f64,
+) -> Vec<Point> {
+    let device = DEVICE.get().expect("Call .init() to load model");
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .generate_class_guided(class, n, guidance_scale, device)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn generate_class_guided(
+    class: usize,
+    n: usize,
+    guidance_scale: f64,
+) -> Vec<Point> {
+    let device = DEVICE.get().expect("Call .init() to load model");
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .generate_class_guided(class, n, guidance_scale, device)
+        .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn predict_label(x: Vec<Point>) -> Vec<f32> {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .predict_label(x)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn predict_label(x: Vec<Point>) -> Vec<f32> {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .predict_label(x)
+        .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn encode_std(x: Vec<Point>) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .encode_std(x)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn encode_std(x: Vec<Point>) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .encode_std(x)
+        .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn encode_latent(x: Vec<Point>, deterministic: bool) -> Vec<Vec<f32>> {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .encode_latent(x, deterministic)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn encode_latent(
+    x: Vec<Point>,
+    deterministic: bool,
+) -> Vec<Vec<f32>> {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .encode_latent(x, deterministic)
+        .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn sample_posterior(
+    x: Vec<Point>,
+    n_samples: usize,
+) -> Vec<Vec<Vec<f32>>> {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .sample_posterior(x, n_samples)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn sample_posterior(
+    x: Vec<Point>,
+    n_samples: usize,
+) -> Vec<Vec<Vec<f32>>> {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .sample_posterior(x, n_samples)
+        .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn score(
+    x: Vec<Point>,
+    labels: Vec<f32>,
+    classes: Option<Vec<usize>>,
+) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .score(x, labels, classes)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn score(
+    x: Vec<Point>,
+    labels: Vec<f32>,
+    classes: Option<Vec<usize>>,
+) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .score(x, labels, classes)
+        .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn calibrate_threshold(
+    x: Vec<Point>,
+    labels: Vec<f32>,
+    classes: Option<Vec<usize>>,
+    percentile: f64,
+) -> f32 {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .calibrate_threshold(x, labels, classes, percentile)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn calibrate_threshold(
+    x: Vec<Point>,
+    labels: Vec<f32>,
+    classes: Option<Vec<usize>>,
+    percentile: f64,
+) -> f32 {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .calibrate_threshold(x, labels, classes, percentile)
+        .await
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub fn encode_quantized(x: Vec<Point>) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .encode_quantized(x)
+}
+
+#[cfg(target_family = "wasm")]
+pub async fn encode_quantized(
+    x: Vec<Point>,
+) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+    MODEL
+        .get()
+        .expect("Call .init() to load model")
+        .encode_quantized(x)
+        .await
+}
+
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
 
@@ -77,13 +313,38 @@ use pyo3::prelude::*;
 #[pymodule]
 fn _burn_vae(_py: Python, m: &PyModule) -> PyResult<()> {
     #[pyfn(m)]
-    fn _init(dir: &str) {
-        init(dir);
+    #[pyo3(signature = (dir, ema=false))]
+    fn _init(dir: &str, ema: bool) {
+        match ema {
+            true => init_with(dir, Weights::Ema),
+            false => init(dir),
+        }
+    }
+
+    #[pyfn(m)]
+    #[pyo3(signature = (t, n, guidance_scale=None))]
+    fn _generate(
+        t: f32,
+        n: usize,
+        guidance_scale: Option<f64>,
+    ) -> Vec<Point> {
+        match guidance_scale {
+            Some(scale) => generate_guided(t, n, scale),
+            None => generate(t, n),
+        }
     }
 
     #[pyfn(m)]
-    fn _generate(t: f32, n: usize) -> Vec<Point> {
-        generate(t, n)
+    #[pyo3(signature = (class, n, guidance_scale=None))]
+    fn _generate_class(
+        class: usize,
+        n: usize,
+        guidance_scale: Option<f64>,
+    ) -> Vec<Point> {
+        match guidance_scale {
+            Some(scale) => generate_class_guided(class, n, scale),
+            None => generate_class(class, n),
+        }
     }
 
     #[pyfn(m)]
@@ -91,5 +352,70 @@ fn _burn_vae(_py: Python, m: &PyModule) -> PyResult<()> {
         MODEL.get().expect("Call .init() to load model").encode(x)
     }
 
+    #[pyfn(m)]
+    fn encode_conditional(
+        x: Vec<Point>,
+        labels: Vec<f32>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        MODEL
+            .get()
+            .expect("Call .init() to load model")
+            .encode_conditional(x, labels)
+    }
+
+    #[pyfn(m)]
+    fn _encode_quantized(x: Vec<Point>) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        encode_quantized(x)
+    }
+
+    #[pyfn(m)]
+    fn _predict_label(x: Vec<Point>) -> Vec<f32> {
+        predict_label(x)
+    }
+
+    #[pyfn(m)]
+    fn _encode_std(x: Vec<Point>) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        encode_std(x)
+    }
+
+    #[pyfn(m)]
+    #[pyo3(signature = (x, deterministic=true))]
+    fn _encode_latent(
+        x: Vec<Point>,
+        deterministic: bool,
+    ) -> Vec<Vec<f32>> {
+        encode_latent(x, deterministic)
+    }
+
+    #[pyfn(m)]
+    #[pyo3(signature = (x, n_samples=1))]
+    fn _sample_posterior(
+        x: Vec<Point>,
+        n_samples: usize,
+    ) -> Vec<Vec<Vec<f32>>> {
+        sample_posterior(x, n_samples)
+    }
+
+    #[pyfn(m)]
+    #[pyo3(signature = (x, labels, classes=None))]
+    fn _score(
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+    ) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        score(x, labels, classes)
+    }
+
+    #[pyfn(m)]
+    #[pyo3(signature = (x, labels, percentile=99., classes=None))]
+    fn _calibrate_threshold(
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        percentile: f64,
+        classes: Option<Vec<usize>>,
+    ) -> f32 {
+        calibrate_threshold(x, labels, classes, percentile)
+    }
+
     Ok(())
 }
@@ -1 +1,12 @@
-from burn_vae._burn_vae import _init as init, _generate as generate
+from burn_vae._burn_vae import (
+    _init as init,
+    _generate as generate,
+    _generate_class as generate_class,
+    _encode_quantized as encode_quantized,
+    _predict_label as predict_label,
+    _encode_std as encode_std,
+    _encode_latent as encode_latent,
+    _sample_posterior as sample_posterior,
+    _score as score,
+    _calibrate_threshold as calibrate_threshold,
+)
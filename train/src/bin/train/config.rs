@@ -1,7 +1,7 @@
 use burn::{nn::LinearConfig, optim::AdamWConfig};
 use dataset::{INPUT_DIM, LABEL_DIM};
 use once_cell::sync::OnceCell;
-use train::TrainingConfig;
+use train::{Optimizer, Scheduler, TrainingConfig};
 use vae::{DecoderConfig, EncoderConfig, MLPBlockConfig, ModelConfig};
 
 const LATENT_DIM: usize = 2;
@@ -30,13 +30,16 @@ pub fn config() -> &'static TrainingConfig {
             )
             .with_kl_weight(1e0)
             .with_latent_dim(LATENT_DIM),
-            AdamWConfig::new(),
+            Optimizer::AdamW(AdamWConfig::new()),
         )
+        .with_scheduler(Scheduler::Cosine {
+            final_fraction: 1e-2,
+        })
         .with_num_epochs(1000)
         .with_batch_size(256)
         .with_num_workers(4)
         .with_warmup_steps(1000)
         .with_early_stop_patience(50)
-        .with_learning_rate(8e1)
+        .with_learning_rate(1e-3)
     })
 }
@@ -12,18 +12,56 @@ fn main() {
     use rand::Rng;
     use train::train;
     use train::{
-        load_model,
+        evaluate::Condition,
+        load_generator,
         visualization::{Trace, plot},
     };
     type Backend = Fusion<Wgpu<AutoGraphicsApi, f32, i32>>;
     const DEVICE: WgpuDevice = WgpuDevice::BestAvailable;
 
-    let artifacts_dir = &std::env::args()
-        .nth(1)
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(String::as_str) == Some("evaluate") {
+        let dir = args[2..]
+            .iter()
+            .find(|arg| !arg.starts_with("--"))
+            .map_or("model_artifacts", String::as_str);
+        let nearest = args.iter().any(|arg| arg == "--nearest");
+        return train::evaluate::evaluate::<Backend>(
+            dir, nearest, &DEVICE,
+        );
+    }
+    if args.get(1).map(String::as_str) == Some("compare-precision") {
+        let dir = args.get(2).map_or("model_artifacts", String::as_str);
+        return train::evaluate::compare_precision::<Backend>(
+            dir, &DEVICE,
+        );
+    }
+    if args.get(1).map(String::as_str) == Some("quantize") {
+        let dir = args.get(2).map_or("model_artifacts", String::as_str);
+        return train::evaluate::compare_quantized::<Backend>(
+            dir, &DEVICE,
+        );
+    }
+    if args.get(1).map(String::as_str) == Some("sweep") {
+        let spec = args.get(2).expect("usage: sweep <spec> [dir]");
+        let dir = args.get(3).map_or("sweeps", String::as_str);
+        return train::sweep::sweep(spec, dir, config());
+    }
+    if args.get(1).map(String::as_str) == Some("trial") {
+        let dir = args.get(2).expect("usage: trial <dir>");
+        let config = train::load_config(dir);
+        return train::<Autodiff<Backend>>(dir, &config, &DEVICE)
+            .expect("Trial should train successfully");
+    }
+
+    let artifacts_dir = &args
+        .get(1)
+        .cloned()
         .unwrap_or("model_artifacts".to_string());
 
-    train::<Autodiff<Backend>>(artifacts_dir, config(), &DEVICE);
-    let model = load_model::<Backend>(artifacts_dir, &DEVICE);
+    train::<Autodiff<Backend>>(artifacts_dir, config(), &DEVICE)
+        .expect("Model should train successfully");
+    let model = load_generator::<Backend>(artifacts_dir, &DEVICE);
 
     const N: usize = 5000;
     const MAX_SIZE: usize = 128;
@@ -41,11 +79,19 @@ fn main() {
     let mut generated = Vec::new();
     let mut gen_colors = Vec::new();
     (0..N).step_by(MAX_SIZE).for_each(|_| {
-        let t = rng.gen_range(min_t..max_t);
-        let current_generated = model.generate(t, MAX_SIZE, &DEVICE);
+        let condition = match model.n_classes() {
+            0 => Condition::Label(rng.gen_range(min_t..max_t)),
+            n_classes => Condition::Class(rng.gen_range(0..n_classes)),
+        };
+        let current_generated =
+            condition.generate(&model, MAX_SIZE, &DEVICE);
         let n = current_generated.len();
+        let color = match condition {
+            Condition::Label(t) => t,
+            Condition::Class(class) => class as f32,
+        };
         generated.extend(current_generated);
-        gen_colors.extend(std::iter::repeat(t).take(n));
+        gen_colors.extend(std::iter::repeat_n(color, n));
     });
 
     plot(&[
@@ -0,0 +1,222 @@
+//! Exponential moving average of the Gaussian VAE's weights. The learner
+//! trains an [`EmaVAE`] whose `ema` copy is blended in by
+//! [`EmaOptimizer`] after every step and used for validation.
+use burn::config::Config;
+#[cfg(not(target_family = "wasm"))]
+use burn::{
+    LearningRate,
+    module::{Module, ModuleMapper, ModuleVisitor, ParamId},
+    optim::{GradientsParams, Optimizer},
+    record::{PrecisionSettings, Record},
+    tensor::{
+        Tensor,
+        backend::{AutodiffBackend, Backend},
+    },
+    train::{TrainOutput, TrainStep, ValidStep},
+};
+#[cfg(not(target_family = "wasm"))]
+use dataset::SpiralBatch;
+#[cfg(not(target_family = "wasm"))]
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_family = "wasm"))]
+use std::collections::VecDeque;
+#[cfg(not(target_family = "wasm"))]
+use vae::{Model, metric::VAEOutput};
+
+#[derive(Config, Debug)]
+pub struct EmaConfig {
+    #[config(default = 0.999)]
+    pub decay: f64,
+    /// The decay ramps up as `(1 + t) / (warmup_steps + t)` until it
+    /// reaches `decay`.
+    #[config(default = 100)]
+    pub warmup_steps: usize,
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[derive(Module, Debug)]
+pub struct EmaVAE<B: Backend> {
+    pub model: Model<B>,
+    pub ema: Model<B>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> EmaVAE<B> {
+    pub fn new(model: Model<B>) -> Self {
+        Self {
+            ema: model.clone().map(&mut Detach),
+            model,
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+struct Detach;
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> ModuleMapper<B> for Detach {
+    fn map_float<const D: usize>(
+        &mut self,
+        _id: &ParamId,
+        tensor: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        tensor.detach()
+    }
+}
+
+/// Collects a module's parameters, flattened, in visiting order.
+#[cfg(not(target_family = "wasm"))]
+struct Collect<B: Backend>(VecDeque<Tensor<B, 1>>);
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> ModuleVisitor<B> for Collect<B> {
+    fn visit_float<const D: usize>(
+        &mut self,
+        _id: &ParamId,
+        tensor: &Tensor<B, D>,
+    ) {
+        let n = tensor.shape().num_elements();
+        self.0.push_back(tensor.clone().detach().reshape([n]));
+    }
+}
+
+/// Moves each parameter towards the collected ones, in the same order.
+#[cfg(not(target_family = "wasm"))]
+struct Blend<B: Backend> {
+    params: VecDeque<Tensor<B, 1>>,
+    decay: f64,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> ModuleMapper<B> for Blend<B> {
+    fn map_float<const D: usize>(
+        &mut self,
+        _id: &ParamId,
+        tensor: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        let param = self
+            .params
+            .pop_front()
+            .expect("EMA and model should have the same parameters")
+            .reshape(tensor.shape());
+        tensor
+            .detach()
+            .mul_scalar(self.decay)
+            .add(param.mul_scalar(1. - self.decay))
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: AutodiffBackend> TrainStep<SpiralBatch<B>, VAEOutput<B>>
+    for EmaVAE<B>
+{
+    fn step(&self, batch: SpiralBatch<B>) -> TrainOutput<VAEOutput<B>> {
+        TrainStep::step(&self.model, batch)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> ValidStep<SpiralBatch<B>, VAEOutput<B>> for EmaVAE<B> {
+    fn step(&self, batch: SpiralBatch<B>) -> VAEOutput<B> {
+        ValidStep::step(&self.ema, batch)
+    }
+}
+
+/// Steps the wrapped optimizer on `model`, then updates `ema`.
+#[cfg(not(target_family = "wasm"))]
+pub struct EmaOptimizer<O> {
+    inner: O,
+    decay: f64,
+    warmup_steps: usize,
+    step: usize,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<O> EmaOptimizer<O> {
+    pub fn new(inner: O, config: &EmaConfig) -> Self {
+        Self {
+            inner,
+            decay: config.decay,
+            warmup_steps: config.warmup_steps,
+            step: 0,
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B, O> Optimizer<EmaVAE<B>, B> for EmaOptimizer<O>
+where
+    B: AutodiffBackend,
+    O: Optimizer<Model<B>, B>,
+{
+    type Record = EmaOptimizerRecord<O::Record>;
+
+    fn step(
+        &mut self,
+        lr: LearningRate,
+        module: EmaVAE<B>,
+        grads: GradientsParams,
+    ) -> EmaVAE<B> {
+        let model = self.inner.step(lr, module.model, grads);
+
+        self.step += 1;
+        let ramp = (1 + self.step) as f64
+            / (self.warmup_steps + self.step) as f64;
+        let mut params = Collect(VecDeque::new());
+        model.visit(&mut params);
+        let ema = module.ema.map(&mut Blend {
+            params: params.0,
+            decay: self.decay.min(ramp),
+        });
+
+        EmaVAE { model, ema }
+    }
+
+    fn to_record(&self) -> Self::Record {
+        EmaOptimizerRecord {
+            inner: self.inner.to_record(),
+            step: self.step,
+        }
+    }
+
+    fn load_record(mut self, record: Self::Record) -> Self {
+        self.inner = self.inner.load_record(record.inner);
+        self.step = record.step;
+        self
+    }
+}
+
+/// Checkpointed [`EmaOptimizer`] state: the wrapped optimizer's record
+/// and the step count driving the decay ramp, so resuming does not
+/// restart the ramp.
+#[cfg(not(target_family = "wasm"))]
+pub struct EmaOptimizerRecord<R> {
+    inner: R,
+    step: usize,
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[derive(Serialize, Deserialize)]
+pub struct EmaOptimizerItem<I> {
+    inner: I,
+    step: usize,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<R: Record> Record for EmaOptimizerRecord<R> {
+    type Item<P: PrecisionSettings> = EmaOptimizerItem<R::Item<P>>;
+
+    fn into_item<P: PrecisionSettings>(self) -> Self::Item<P> {
+        EmaOptimizerItem {
+            inner: self.inner.into_item(),
+            step: self.step,
+        }
+    }
+
+    fn from_item<P: PrecisionSettings>(item: Self::Item<P>) -> Self {
+        Self {
+            inner: R::from_item(item.inner),
+            step: item.step,
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use crate::{
+    load::{load_config, load_generator},
+    precision::{Precision, generator_from_bytes},
+    quantize::{generator_from_quantized, quantize_generator},
+};
+use burn::tensor::backend::Backend;
+use dataset::{DataSource, Point, T_MAX, T_MIN, get_classified_data_from};
+use std::fmt;
+use vae::{
+    Generator,
+    quality::{
+        mmd, nearest_class_distance, nearest_slice_distance,
+        sliced_wasserstein,
+    },
+};
+
+/// Evenly spaced labels across the training range.
+pub fn label_grid(n: usize) -> Vec<f32> {
+    let step = (T_MAX - T_MIN) / n.saturating_sub(1).max(1) as f32;
+    (0..n).map(|i| T_MIN + step * i as f32).collect()
+}
+
+/// What generation is conditioned on: a label, or the class of a
+/// categorically conditioned model.
+#[derive(Clone, Copy, Debug)]
+pub enum Condition {
+    Label(f32),
+    Class(usize),
+}
+
+impl Condition {
+    /// `n` evenly spaced labels, or every class of a categorical model.
+    pub fn grid<B: Backend>(
+        generator: &Generator<B>,
+        n: usize,
+    ) -> Vec<Self> {
+        match generator.n_classes() {
+            0 => label_grid(n).into_iter().map(Self::Label).collect(),
+            n_classes => (0..n_classes).map(Self::Class).collect(),
+        }
+    }
+
+    pub fn generate<B: Backend>(
+        &self,
+        generator: &Generator<B>,
+        n: usize,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        match self {
+            Self::Label(t) => generator.generate(*t, n, device),
+            Self::Class(class) => {
+                generator.generate_class(*class, n, device)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Label(t) => write!(f, "t={t:.2}"),
+            Self::Class(class) => write!(f, "class {class}"),
+        }
+    }
+}
+
+/// Reference points used instead of the analytic slice.
+pub struct Reference {
+    pub points: Vec<Point>,
+    pub labels: Vec<f32>,
+    pub classes: Vec<usize>,
+    pub tolerance: f32,
+}
+
+/// Mean distance from `n` samples generated at each condition in `grid`
+/// to the true data under it: the slice at a label or the arm of a
+/// class, measured analytically or against `reference`.
+pub fn conditional_fidelity<B: Backend>(
+    generator: &Generator<B>,
+    source: &DataSource,
+    grid: &[Condition],
+    n: usize,
+    reference: Option<&Reference>,
+    device: &B::Device,
+) -> Vec<f64> {
+    grid.iter()
+        .map(|condition| {
+            let samples = condition.generate(generator, n, device);
+            samples
+                .iter()
+                .map(|point| match (condition, reference) {
+                    (Condition::Label(t), Some(reference)) => {
+                        nearest_slice_distance(
+                            point,
+                            *t,
+                            &reference.points,
+                            &reference.labels,
+                            reference.tolerance,
+                        )
+                    }
+                    (Condition::Label(t), None) => {
+                        source.slice_distance(point, *t) as f64
+                    }
+                    (Condition::Class(class), Some(reference)) => {
+                        nearest_class_distance(
+                            point,
+                            *class,
+                            &reference.points,
+                            &reference.classes,
+                        )
+                    }
+                    (Condition::Class(class), None) => {
+                        source.arm_distance(point, *class) as f64
+                    }
+                })
+                .sum::<f64>()
+                / samples.len().max(1) as f64
+        })
+        .collect()
+}
+
+/// Prints conditional fidelity across a label grid for the model in `dir`.
+pub fn evaluate<B: Backend>(dir: &str, nearest: bool, device: &B::Device) {
+    const GRID: usize = 10;
+    const N: usize = 256;
+
+    let config = load_config(dir);
+    let generator = load_generator::<B>(dir, device);
+    let reference = nearest.then(|| {
+        let (points, labels, classes) =
+            get_classified_data_from(20_000, config.data.clone());
+        Reference {
+            points,
+            labels,
+            classes,
+            tolerance: (T_MAX - T_MIN) / (2 * GRID) as f32,
+        }
+    });
+
+    let grid = Condition::grid(&generator, GRID);
+    let fidelity = conditional_fidelity(
+        &generator,
+        &config.data,
+        &grid,
+        N,
+        reference.as_ref(),
+        device,
+    );
+
+    println!("{:>10} {:>12}", "condition", "distance");
+    grid.iter()
+        .zip(&fidelity)
+        .for_each(|(condition, distance)| {
+            println!("{:>10} {distance:>12.4}", condition.to_string());
+        });
+    println!(
+        "{:>10} {:>12.4}",
+        "mean",
+        fidelity.iter().sum::<f64>() / fidelity.len().max(1) as f64
+    );
+}
+
+struct PrecisionReport {
+    precision: Precision,
+    size: usize,
+    recon: Option<f64>,
+    fidelity: f64,
+    mmd: f64,
+}
+
+/// Compares the model in `dir` at full precision against its weights
+/// rounded to each lower precision: export size, reconstruction loss on
+/// held-out data, conditional fidelity and MMD of generated samples.
+/// Every precision draws samples from the same seed.
+pub fn compare_precision<B: Backend>(dir: &str, device: &B::Device) {
+    const GRID: usize = 10;
+    const N: usize = 256;
+    const SEED: u64 = 0;
+
+    let config = load_config(dir);
+    let full_path = match config.export_precision {
+        Precision::Full => format!("{dir}/model.bin"),
+        _ => format!("{dir}/model.full.bin"),
+    };
+    let bytes = std::fs::read(full_path)
+        .expect("Full-precision weights not found");
+    let (points, labels, classes) =
+        get_classified_data_from(2048, config.data.clone());
+
+    let reports = [Precision::Full, Precision::Half, Precision::BFloat16]
+        .into_iter()
+        .map(|precision| {
+            let generator = generator_from_bytes::<B>(
+                &config,
+                bytes.clone(),
+                &Precision::Full,
+                &precision,
+                device,
+            );
+            let size = precision.generator_to_bytes(&generator).len();
+            let grid = Condition::grid(&generator, GRID);
+            let recon =
+                matches!(generator, Generator::Gaussian(_)).then(|| {
+                    let (_, recon, _) = generator.score(
+                        points.clone(),
+                        labels.clone(),
+                        (generator.n_classes() > 0)
+                            .then(|| classes.clone()),
+                    );
+                    recon.iter().map(|x| *x as f64).sum::<f64>()
+                        / recon.len().max(1) as f64
+                });
+
+            B::seed(SEED);
+            let fidelity = conditional_fidelity(
+                &generator,
+                &config.data,
+                &grid,
+                N,
+                None,
+                device,
+            );
+            B::seed(SEED);
+            let samples = grid
+                .iter()
+                .flat_map(|condition| {
+                    condition.generate(&generator, N, device)
+                })
+                .collect::<Vec<_>>();
+
+            PrecisionReport {
+                precision,
+                size,
+                recon,
+                fidelity: fidelity.iter().sum::<f64>()
+                    / fidelity.len().max(1) as f64,
+                mmd: mmd(&samples, &points, None),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let full = &reports[0];
+    let delta = |value: f64, reference: f64| {
+        format!("{value:.5} ({:+.1e})", value - reference)
+    };
+    println!(
+        "{:>6} {:>10} {:>22} {:>22} {:>22}",
+        "prec", "size (KB)", "recon", "fidelity", "mmd"
+    );
+    reports.iter().for_each(|report| {
+        let recon = report
+            .recon
+            .zip(full.recon)
+            .map_or("n/a".to_string(), |(r, f)| delta(r, f));
+        println!(
+            "{:>6} {:>10.1} {recon:>22} {:>22} {:>22}",
+            report.precision.name(),
+            report.size as f64 / 1024.,
+            delta(report.fidelity, full.fidelity),
+            delta(report.mmd, full.mmd),
+        );
+    });
+}
+
+/// Writes `model.q8.bin` from the trained weights and reports how far
+/// samples from the dequantized model drift from full precision.
+pub fn compare_quantized<B: Backend>(dir: &str, device: &B::Device) {
+    const GRID: usize = 10;
+    const N: usize = 256;
+    const SEED: u64 = 0;
+    const PROJECTIONS: usize = 64;
+
+    let config = load_config(dir);
+    let full = load_generator::<B>(dir, device);
+    let bytes = quantize_generator(&full);
+    std::fs::write(format!("{dir}/model.q8.bin"), &bytes)
+        .expect("Quantized weights should be saved successfully");
+    let quantized = generator_from_quantized::<B>(&config, &bytes, device);
+    let full_size = Precision::Full.generator_to_bytes(&full).len();
+
+    let grid = Condition::grid(&full, GRID);
+    let sample = |generator: &Generator<B>| {
+        B::seed(SEED);
+        let fidelity = conditional_fidelity(
+            generator,
+            &config.data,
+            &grid,
+            N,
+            None,
+            device,
+        );
+        B::seed(SEED);
+        let samples = grid
+            .iter()
+            .flat_map(|condition| condition.generate(generator, N, device))
+            .collect::<Vec<_>>();
+        (
+            fidelity.iter().sum::<f64>() / fidelity.len().max(1) as f64,
+            samples,
+        )
+    };
+    let (full_fidelity, full_samples) = sample(&full);
+    let (q8_fidelity, q8_samples) = sample(&quantized);
+
+    println!(
+        "size (KB): {:.1} -> {:.1}",
+        full_size as f64 / 1024.,
+        bytes.len() as f64 / 1024.
+    );
+    println!(
+        "fidelity: {full_fidelity:.5} -> {q8_fidelity:.5} ({:+.1e})",
+        q8_fidelity - full_fidelity
+    );
+    println!("mmd: {:.2e}", mmd(&q8_samples, &full_samples, None));
+    println!(
+        "sliced wasserstein: {:.2e}",
+        sliced_wasserstein(&q8_samples, &full_samples, PROJECTIONS)
+    );
+}
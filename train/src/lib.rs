@@ -1,12 +1,42 @@
 #[cfg(not(target_family = "wasm"))]
+pub mod logger;
+#[cfg(not(target_family = "wasm"))]
 pub mod metric;
+#[cfg(not(target_family = "wasm"))]
+pub mod tensorboard;
 pub mod visualization;
 
+mod optim;
+pub use optim::{Optimizer, Scheduler};
+#[cfg(not(target_family = "wasm"))]
+pub use optim::AnyOptimizer;
+
+mod precision;
+pub use precision::{
+    BFloat16PrecisionSettings, Precision, generator_from_bytes,
+};
+
+pub mod quantize;
+pub use quantize::generator_from_quantized;
+
+mod ema;
+pub use ema::EmaConfig;
+#[cfg(not(target_family = "wasm"))]
+pub use ema::{EmaOptimizer, EmaVAE};
+
 mod train;
-pub use train::TrainingConfig;
+pub use train::{Architecture, TrainingConfig};
 
 #[cfg(not(target_family = "wasm"))]
 pub use train::train;
 
 mod load;
-pub use load::load_model;
+pub use load::{
+    Weights, load_config, load_generator, load_generator_with, load_model,
+    load_model_with,
+};
+
+#[cfg(not(target_family = "wasm"))]
+pub mod evaluate;
+#[cfg(not(target_family = "wasm"))]
+pub mod sweep;
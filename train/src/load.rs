@@ -1,17 +1,89 @@
+use crate::{Architecture, TrainingConfig};
 use burn::{
     config::Config,
     module::Module,
-    record::Recorder,
+    record::{Record, Recorder},
     tensor::{Device, backend::Backend},
 };
-use vae::Model;
+use vae::{Generator, Model};
+
+pub fn load_config(dir: &str) -> TrainingConfig {
+    TrainingConfig::load(format!("{dir}/config.json"))
+        .expect("Config file not found")
+}
+
+/// Which saved weights to load.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Weights {
+    #[default]
+    Trained,
+    /// The EMA of the weights, saved when training with `ema`.
+    Ema,
+}
+
+impl Weights {
+    pub fn file(&self) -> &'static str {
+        match self {
+            Self::Trained => "model",
+            Self::Ema => "model_ema",
+        }
+    }
+}
+
+fn load_record<R: Record>(dir: &str, weights: Weights) -> R {
+    burn::record::CompactRecorder::new()
+        .load(format!("{dir}/{}", weights.file()).into())
+        .expect("Model not found")
+}
 
 pub fn load_model<B: Backend>(dir: &str, device: &Device<B>) -> Model<B> {
-    let config = crate::TrainingConfig::load(format!("{dir}/config.json"))
-        .expect("Config file not found");
-    let record = burn::record::CompactRecorder::new()
-        .load(format!("{dir}/model").into())
-        .expect("Model not found");
+    load_model_with(dir, Weights::Trained, device)
+}
+
+pub fn load_model_with<B: Backend>(
+    dir: &str,
+    weights: Weights,
+    device: &Device<B>,
+) -> Model<B> {
+    let config = load_config(dir);
+    config
+        .model
+        .init_with::<B>(load_record(dir, weights))
+        .to_device(device)
+}
+
+pub fn load_generator<B: Backend>(
+    dir: &str,
+    device: &Device<B>,
+) -> Generator<B> {
+    load_generator_with(dir, Weights::Trained, device)
+}
 
-    config.model.init_with::<B>(record).to_device(device)
+/// EMA weights are only saved for the Gaussian VAE.
+pub fn load_generator_with<B: Backend>(
+    dir: &str,
+    weights: Weights,
+    device: &Device<B>,
+) -> Generator<B> {
+    let config = load_config(dir);
+    match &config.architecture {
+        Architecture::Gaussian => {
+            load_model_with(dir, weights, device).into()
+        }
+        _ if weights == Weights::Ema => {
+            panic!("EMA weights are only saved for the Gaussian VAE")
+        }
+        Architecture::VectorQuantized(vq) => vq
+            .init_with::<B>(load_record(dir, weights))
+            .to_device(device)
+            .into(),
+        Architecture::Flow(flow) => flow
+            .init_with::<B>(load_record(dir, weights))
+            .to_device(device)
+            .into(),
+        Architecture::Diffusion(ddpm) => ddpm
+            .init_with::<B>(load_record(dir, weights))
+            .to_device(device)
+            .into(),
+    }
 }
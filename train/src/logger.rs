@@ -0,0 +1,291 @@
+//! Per-epoch metric aggregates written to `metrics.jsonl`,
+//! `metrics.csv`, `summary.json` and optionally TensorBoard event files,
+//! next to burn's own file logs. The files are rewritten from the run's
+//! history, so every epoch and split appears once.
+use crate::tensorboard::EventWriter;
+use burn::train::{
+    logger::{FileMetricLogger, MetricLogger},
+    metric::MetricEntry,
+    renderer::{MetricState, MetricsRenderer, TrainingProgress},
+};
+use serde_json::{Value, json};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+type Epochs = BTreeMap<usize, BTreeMap<String, f64>>;
+
+#[derive(Default)]
+struct History {
+    train: Epochs,
+    valid: Epochs,
+}
+
+impl History {
+    /// Epochs up to `last` from the `metrics.jsonl` of an earlier run in
+    /// `dir`, kept when training resumes from a checkpoint.
+    fn resume(dir: &str, last: usize) -> Self {
+        let mut history = Self::default();
+        std::fs::read_to_string(format!("{dir}/metrics.jsonl"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .for_each(|record| {
+                let epoch = record["epoch"].as_u64().map(|e| e as usize);
+                let metrics = serde_json::from_value::<BTreeMap<_, _>>(
+                    record["metrics"].clone(),
+                );
+                let epochs = match record["split"].as_str() {
+                    Some("train") => &mut history.train,
+                    Some("valid") => &mut history.valid,
+                    _ => return,
+                };
+                match (epoch, metrics) {
+                    (Some(epoch), Ok(metrics)) if epoch <= last => {
+                        epochs.insert(epoch, metrics);
+                    }
+                    _ => {}
+                }
+            });
+        history
+    }
+
+    /// Rows ordered by epoch, train before valid.
+    fn rows(&self) -> Vec<(usize, &'static str, &BTreeMap<String, f64>)> {
+        let mut rows = self
+            .train
+            .iter()
+            .map(|(epoch, metrics)| (*epoch, "train", metrics))
+            .chain(
+                self.valid
+                    .iter()
+                    .map(|(epoch, metrics)| (*epoch, "valid", metrics)),
+            )
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|(epoch, split, _)| (*epoch, *split == "valid"));
+        rows
+    }
+
+    fn write(&self, dir: &str) {
+        let rows = self.rows();
+        let jsonl = rows
+            .iter()
+            .map(|(epoch, split, metrics)| {
+                json!({
+                    "epoch": epoch,
+                    "split": split,
+                    "metrics": metrics,
+                })
+                .to_string()
+                    + "\n"
+            })
+            .collect::<String>();
+        let csv =
+            std::iter::once("epoch,split,metric,value\n".to_string())
+                .chain(rows.iter().flat_map(|(epoch, split, metrics)| {
+                    metrics.iter().map(move |(name, value)| {
+                        format!("{epoch},{split},\"{name}\",{value}\n")
+                    })
+                }))
+                .collect::<String>();
+        std::fs::write(format!("{dir}/metrics.jsonl"), jsonl)
+            .expect("to write metrics.jsonl");
+        std::fs::write(format!("{dir}/metrics.csv"), csv)
+            .expect("to write metrics.csv");
+    }
+}
+
+/// Wraps burn's [`FileMetricLogger`] for one split and records the mean
+/// of every numeric metric in the structured logs at the end of each
+/// epoch.
+pub struct StructuredLogger {
+    inner: FileMetricLogger,
+    dir: String,
+    split: &'static str,
+    // Running (sum, count) per metric for the current epoch.
+    current: BTreeMap<String, (f64, usize)>,
+    history: Arc<Mutex<History>>,
+    tensorboard: Option<EventWriter>,
+}
+
+/// Train and valid loggers sharing the run's history. Resuming from
+/// `checkpoint` keeps the epochs logged up to it and drops the rest.
+/// With `tensorboard`, scalars also go to `tensorboard/{train,valid}`.
+pub fn loggers(
+    dir: &str,
+    tensorboard: bool,
+    checkpoint: Option<usize>,
+) -> (StructuredLogger, StructuredLogger) {
+    let history = History::resume(dir, checkpoint.unwrap_or(0));
+    history.write(dir);
+    let history = Arc::new(Mutex::new(history));
+    let logger = |split| StructuredLogger {
+        inner: FileMetricLogger::new(&format!("{dir}/{split}")),
+        dir: dir.to_string(),
+        split,
+        current: BTreeMap::new(),
+        history: history.clone(),
+        tensorboard: tensorboard.then(|| {
+            EventWriter::new(&format!("{dir}/tensorboard/{split}"), "")
+                .expect("to create tensorboard event file")
+        }),
+    };
+
+    (logger("train"), logger("valid"))
+}
+
+/// Parses a serialized numeric entry, skipping non-numeric and
+/// non-finite values.
+fn parse(serialized: &str) -> Option<f64> {
+    serialized
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+}
+
+impl StructuredLogger {
+    fn write_summary(&self, history: &History) {
+        let (split, epochs) = match history.valid.is_empty() {
+            true => ("train", &history.train),
+            false => ("valid", &history.valid),
+        };
+        let best = epochs
+            .iter()
+            .filter_map(|(epoch, metrics)| {
+                metrics.get("Loss").map(|loss| (*epoch, *loss))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        let summary = json!({
+            "epochs": epochs.len(),
+            "selection": format!("lowest {split} Loss"),
+            "best_epoch": best.map(|(epoch, _)| epoch),
+            "best": best.map(|(epoch, _)| json!({
+                "train": history.train.get(&epoch),
+                "valid": history.valid.get(&epoch),
+            })),
+        });
+        std::fs::write(
+            format!("{}/summary.json", self.dir),
+            serde_json::to_string_pretty(&summary)
+                .expect("to serialize summary"),
+        )
+        .expect("to write summary.json");
+    }
+}
+
+impl MetricLogger for StructuredLogger {
+    fn log(&mut self, item: &MetricEntry) {
+        if let Some(value) = parse(&item.serialize) {
+            let (sum, count) =
+                self.current.entry(item.name.clone()).or_default();
+            *sum += value;
+            *count += 1;
+        }
+        self.inner.log(item);
+    }
+
+    fn end_epoch(&mut self, epoch: usize) {
+        // burn ends validation epochs one past the epoch they belong to.
+        let logged = match self.split {
+            "valid" => epoch.saturating_sub(1),
+            _ => epoch,
+        };
+        let metrics = std::mem::take(&mut self.current)
+            .into_iter()
+            .map(|(name, (sum, count))| (name, sum / count as f64))
+            .collect::<BTreeMap<_, _>>();
+
+        if let Some(writer) = self.tensorboard.as_mut() {
+            metrics.iter().for_each(|(name, value)| {
+                writer
+                    .scalar(name, logged, *value)
+                    .expect("to write tensorboard event");
+            });
+        }
+
+        let mut history = self.history.lock().expect("history lock");
+        match self.split {
+            "valid" => history.valid.insert(logged, metrics),
+            _ => history.train.insert(logged, metrics),
+        };
+        history.write(&self.dir);
+        self.write_summary(&history);
+
+        self.inner.end_epoch(epoch);
+    }
+
+    fn read_numeric(
+        &mut self,
+        name: &str,
+        epoch: usize,
+    ) -> Result<Vec<f64>, String> {
+        self.inner.read_numeric(name, epoch)
+    }
+}
+
+/// Renders progress as one plain line per split and epoch, for runs
+/// without a terminal where burn's TUI cannot start.
+#[derive(Default)]
+pub struct LogRenderer {
+    train: BTreeMap<String, String>,
+    valid: BTreeMap<String, String>,
+}
+
+impl LogRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn render(
+        metrics: &BTreeMap<String, String>,
+        split: &str,
+        item: &TrainingProgress,
+    ) {
+        if item.progress.items_processed < item.progress.items_total {
+            return;
+        }
+        let metrics = metrics
+            .iter()
+            .map(|(name, value)| format!("{name} {value}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        println!(
+            "[{split}] epoch {}/{}: {metrics}",
+            item.epoch, item.epoch_total
+        );
+    }
+}
+
+fn record(metrics: &mut BTreeMap<String, String>, state: MetricState) {
+    let (entry, value) = match state {
+        MetricState::Numeric(entry, value) => {
+            (entry, format!("{value:.5}"))
+        }
+        MetricState::Generic(entry) => {
+            let value = entry.serialize.clone();
+            (entry, value)
+        }
+    };
+    metrics.insert(entry.name, value);
+}
+
+impl MetricsRenderer for LogRenderer {
+    fn update_train(&mut self, state: MetricState) {
+        record(&mut self.train, state);
+    }
+
+    fn update_valid(&mut self, state: MetricState) {
+        record(&mut self.valid, state);
+    }
+
+    fn render_train(&mut self, item: TrainingProgress) {
+        Self::render(&self.train, "train", &item);
+    }
+
+    fn render_valid(&mut self, item: TrainingProgress) {
+        Self::render(&self.valid, "valid", &item);
+    }
+}
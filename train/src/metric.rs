@@ -1,29 +1,299 @@
+//! System resource metrics. CPU and memory are read from `/proc` and
+//! report "n/a" where it is unavailable; GPU utilisation comes from a
+//! pluggable [`GpuProvider`] and is only registered when one is found.
 use burn::train::metric::{
     Metric, MetricEntry, MetricMetadata, Numeric,
     state::{FormatOptions, NumericMetricState},
 };
+use once_cell::sync::Lazy;
+use std::time::{Duration, Instant};
 
-pub struct NvidiaUtilMetric {
+/// Kernel clock ticks per second, the unit of `/proc/self/stat` times.
+static CLOCK_TICKS: Lazy<Option<f64>> = Lazy::new(|| {
+    #[cfg(unix)]
+    {
+        // SAFETY: sysconf only reads a system configuration value.
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        (ticks > 0).then_some(ticks as f64)
+    }
+    #[cfg(not(unix))]
+    None
+});
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+fn unavailable(name: &str) -> MetricEntry {
+    MetricEntry::new(
+        name.to_string(),
+        format!("{name}: n/a"),
+        f64::NAN.to_string(),
+    )
+}
+
+/// Reads `/proc/self/stat` fields given in clock ticks, as seconds.
+fn process_stat_seconds<const N: usize>(
+    fields: [usize; N],
+) -> Option<[f64; N]> {
+    let ticks = (*CLOCK_TICKS)?;
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // Fields after the command name start at `state` (field 3), so
+    // field n sits at index n - 3.
+    let values = stat
+        .rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    let mut seconds = [0.; N];
+    for (seconds, field) in seconds.iter_mut().zip(fields) {
+        *seconds = values.get(field - 3)?.parse::<f64>().ok()? / ticks;
+    }
+    Some(seconds)
+}
+
+/// User plus system CPU time (fields 14 and 15).
+fn process_cpu_seconds() -> Option<f64> {
+    process_stat_seconds([14, 15]).map(|[utime, stime]| utime + stime)
+}
+
+/// Mean CPU usage since the process started (field 22), which stands in
+/// until a full sample interval has passed.
+fn process_lifetime_cpu_percent(cpu: f64) -> Option<f64> {
+    let [start] = process_stat_seconds([22])?;
+    let uptime = std::fs::read_to_string("/proc/uptime")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse::<f64>()
+        .ok()?;
+    let elapsed = uptime - start;
+    (elapsed > 0.).then(|| 100. * cpu / elapsed)
+}
+
+fn process_rss_bytes() -> Option<f64> {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .map(|kb| kb * 1024.)
+}
+
+/// Process CPU time as a percentage of one core, sampled at most once a
+/// second. Before the first full second, the process's mean usage since
+/// it started is reported instead.
+#[derive(Default)]
+pub struct CpuUsageMetric {
+    state: NumericMetricState,
+    last: Option<(Instant, f64)>,
+    value: f64,
+}
+
+impl CpuUsageMetric {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Metric for CpuUsageMetric {
+    const NAME: &'static str = "CPU Usage";
+    type Input = ();
+
+    fn update(
+        &mut self,
+        _item: &(),
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let Some(cpu) = process_cpu_seconds() else {
+            return unavailable(Self::NAME);
+        };
+        let now = Instant::now();
+        match self.last {
+            Some((then, last_cpu)) => {
+                let elapsed = now.duration_since(then);
+                if elapsed >= SAMPLE_INTERVAL {
+                    self.value =
+                        100. * (cpu - last_cpu) / elapsed.as_secs_f64();
+                    self.last = Some((now, cpu));
+                }
+            }
+            None => {
+                self.value =
+                    process_lifetime_cpu_percent(cpu).unwrap_or(0.);
+                self.last = Some((now, cpu));
+            }
+        }
+
+        self.state.update(
+            self.value,
+            1,
+            FormatOptions::new(Self::NAME).unit("%").precision(1),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.state.reset();
+    }
+}
+
+impl Numeric for CpuUsageMetric {
+    fn value(&self) -> f64 {
+        self.state.value()
+    }
+}
+
+/// Resident set size of the training process.
+#[derive(Default)]
+pub struct MemoryUsageMetric {
     state: NumericMetricState,
-    has_gpu: bool,
 }
 
-impl NvidiaUtilMetric {
+impl MemoryUsageMetric {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Metric for MemoryUsageMetric {
+    const NAME: &'static str = "Memory (RSS)";
+    type Input = ();
+
+    fn update(
+        &mut self,
+        _item: &(),
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        match process_rss_bytes() {
+            Some(rss) => self.state.update(
+                rss / 1024. / 1024.,
+                1,
+                FormatOptions::new(Self::NAME).unit("MB").precision(0),
+            ),
+            None => unavailable(Self::NAME),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.state.reset();
+    }
+}
+
+impl Numeric for MemoryUsageMetric {
+    fn value(&self) -> f64 {
+        self.state.value()
+    }
+}
+
+/// Samples and batches per second since the start of the epoch, from the
+/// learner's progress counters. The numeric value is samples per second.
+#[derive(Default)]
+pub struct ThroughputMetric {
+    start: Option<(Instant, usize, usize)>,
+    value: f64,
+}
+
+impl ThroughputMetric {
     pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Metric for ThroughputMetric {
+    const NAME: &'static str = "Throughput";
+    type Input = ();
+
+    fn update(
+        &mut self,
+        _item: &(),
+        metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let (items, batches) =
+            (metadata.progress.items_processed, metadata.iteration);
+        let (start, items_0, batches_0) =
+            *self.start.get_or_insert((Instant::now(), items, batches));
+        let elapsed = start.elapsed().as_secs_f64().max(1e-9);
+
+        self.value = items.saturating_sub(items_0) as f64 / elapsed;
+        let batch_rate =
+            batches.saturating_sub(batches_0) as f64 / elapsed;
+        MetricEntry::new(
+            Self::NAME.to_string(),
+            format!(
+                "{}: {:.0} samples/s ({batch_rate:.1} batches/s)",
+                Self::NAME,
+                self.value
+            ),
+            self.value.to_string(),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.start = None;
+        self.value = 0.;
+    }
+}
+
+impl Numeric for ThroughputMetric {
+    fn value(&self) -> f64 {
+        self.value
+    }
+}
+
+/// Source of GPU utilisation readings, in percent.
+pub trait GpuProvider: Send + Sync {
+    fn utilization(&mut self) -> Option<f64>;
+}
+
+/// Queries `nvidia-smi`, which must be on the `PATH`.
+pub struct NvidiaSmi;
+
+impl NvidiaSmi {
+    pub fn detect() -> Option<Self> {
+        let mut provider = Self;
+        provider.utilization().map(|_| provider)
+    }
+}
+
+impl GpuProvider for NvidiaSmi {
+    fn utilization(&mut self) -> Option<f64> {
+        std::process::Command::new("nvidia-smi")
+            .arg("--query-gpu=utilization.gpu")
+            .arg("--format=csv,noheader,nounits")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .and_then(|output| {
+                output.lines().next()?.trim().parse::<f64>().ok()
+            })
+    }
+}
+
+/// GPU utilisation from a [`GpuProvider`], sampled at most once a second.
+pub struct GpuUsageMetric {
+    state: NumericMetricState,
+    provider: Box<dyn GpuProvider>,
+    last: Option<(Instant, Option<f64>)>,
+}
+
+impl GpuUsageMetric {
+    pub fn new(provider: impl GpuProvider + 'static) -> Self {
         Self {
             state: NumericMetricState::new(),
-            has_gpu: true,
+            provider: Box::new(provider),
+            last: None,
         }
     }
-}
 
-impl Default for NvidiaUtilMetric {
-    fn default() -> Self {
-        Self::new()
+    /// Uses the first provider that responds on this machine.
+    pub fn detect() -> Option<Self> {
+        NvidiaSmi::detect().map(Self::new)
     }
 }
 
-impl Metric for NvidiaUtilMetric {
+impl Metric for GpuUsageMetric {
     const NAME: &'static str = "GPU Utilization";
     type Input = ();
 
@@ -32,27 +302,22 @@ impl Metric for NvidiaUtilMetric {
         _item: &(),
         _metadata: &MetricMetadata,
     ) -> MetricEntry {
-        let fmt = FormatOptions::new(Self::NAME).precision(2);
-        let util = self
-            .has_gpu
-            .then(|| {
-                std::process::Command::new("nvidia-smi")
-                    .arg("--query-gpu=utilization.gpu")
-                    .arg("--format=csv,noheader,nounits")
-                    .output()
-                    .ok()
-                    .and_then(|output| {
-                        String::from_utf8(output.stdout).ok()
-                    })
-                    .and_then(|output| output.trim().parse::<f64>().ok())
-                    .unwrap_or_else(|| {
-                        self.has_gpu = false;
-                        0.
-                    })
-            })
-            .unwrap_or(0.);
+        let stale = self
+            .last
+            .is_none_or(|(then, _)| then.elapsed() >= SAMPLE_INTERVAL);
+        if stale {
+            self.last =
+                Some((Instant::now(), self.provider.utilization()));
+        }
 
-        self.state.update(util, 1, fmt)
+        match self.last.and_then(|(_, util)| util) {
+            Some(util) => self.state.update(
+                util,
+                1,
+                FormatOptions::new(Self::NAME).unit("%").precision(1),
+            ),
+            None => unavailable(Self::NAME),
+        }
     }
 
     fn clear(&mut self) {
@@ -60,7 +325,7 @@ impl Metric for NvidiaUtilMetric {
     }
 }
 
-impl Numeric for NvidiaUtilMetric {
+impl Numeric for GpuUsageMetric {
     fn value(&self) -> f64 {
         self.state.value()
     }
@@ -0,0 +1,290 @@
+#[cfg(not(target_family = "wasm"))]
+use burn::{
+    LearningRate,
+    lr_scheduler::LrScheduler,
+    module::AutodiffModule,
+    optim::GradientsParams,
+    record::{PrecisionSettings, Record},
+    tensor::backend::AutodiffBackend,
+};
+use burn::{
+    config::Config,
+    grad_clipping::GradientClippingConfig,
+    optim::{AdamConfig, AdamWConfig, SgdConfig},
+};
+#[cfg(not(target_family = "wasm"))]
+use serde::{Deserialize, Serialize};
+
+#[derive(Config)]
+pub enum Optimizer {
+    Adam(AdamConfig),
+    AdamW(AdamWConfig),
+    /// Momentum is configured on the `SgdConfig`.
+    Sgd(SgdConfig),
+}
+
+impl Optimizer {
+    /// Overrides the optimizer's own gradient clipping when `clipping` is
+    /// set.
+    pub fn with_grad_clipping(
+        &self,
+        clipping: &Option<GradientClippingConfig>,
+    ) -> Self {
+        match (self, clipping.clone()) {
+            (_, None) => self.clone(),
+            (Self::Adam(adam), clipping) => {
+                Self::Adam(adam.clone().with_grad_clipping(clipping))
+            }
+            (Self::AdamW(adamw), clipping) => {
+                Self::AdamW(adamw.clone().with_grad_clipping(clipping))
+            }
+            (Self::Sgd(sgd), clipping) => {
+                Self::Sgd(sgd.clone().with_gradient_clipping(clipping))
+            }
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Optimizer {
+    pub fn init<B: AutodiffBackend, M: AutodiffModule<B>>(
+        &self,
+    ) -> AnyOptimizer<
+        impl burn::optim::Optimizer<M, B>,
+        impl burn::optim::Optimizer<M, B>,
+        impl burn::optim::Optimizer<M, B>,
+    > {
+        match self {
+            Self::Adam(adam) => AnyOptimizer::Adam(adam.init()),
+            Self::AdamW(adamw) => AnyOptimizer::AdamW(adamw.init()),
+            Self::Sgd(sgd) => AnyOptimizer::Sgd(sgd.init()),
+        }
+    }
+}
+
+/// Whichever [`Optimizer`] was configured, behind a single type so the
+/// learner is built once for all of them.
+#[cfg(not(target_family = "wasm"))]
+pub enum AnyOptimizer<A, W, S> {
+    Adam(A),
+    AdamW(W),
+    Sgd(S),
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B, M, A, W, S> burn::optim::Optimizer<M, B> for AnyOptimizer<A, W, S>
+where
+    B: AutodiffBackend,
+    M: AutodiffModule<B>,
+    A: burn::optim::Optimizer<M, B>,
+    W: burn::optim::Optimizer<M, B>,
+    S: burn::optim::Optimizer<M, B>,
+{
+    type Record = AnyOptimizer<A::Record, W::Record, S::Record>;
+
+    fn step(
+        &mut self,
+        lr: LearningRate,
+        module: M,
+        grads: GradientsParams,
+    ) -> M {
+        match self {
+            Self::Adam(optim) => optim.step(lr, module, grads),
+            Self::AdamW(optim) => optim.step(lr, module, grads),
+            Self::Sgd(optim) => optim.step(lr, module, grads),
+        }
+    }
+
+    fn to_record(&self) -> Self::Record {
+        match self {
+            Self::Adam(optim) => AnyOptimizer::Adam(optim.to_record()),
+            Self::AdamW(optim) => AnyOptimizer::AdamW(optim.to_record()),
+            Self::Sgd(optim) => AnyOptimizer::Sgd(optim.to_record()),
+        }
+    }
+
+    fn load_record(self, record: Self::Record) -> Self {
+        match (self, record) {
+            (Self::Adam(optim), AnyOptimizer::Adam(record)) => {
+                Self::Adam(optim.load_record(record))
+            }
+            (Self::AdamW(optim), AnyOptimizer::AdamW(record)) => {
+                Self::AdamW(optim.load_record(record))
+            }
+            (Self::Sgd(optim), AnyOptimizer::Sgd(record)) => {
+                Self::Sgd(optim.load_record(record))
+            }
+            _ => panic!(
+                "Checkpointed optimizer state is for a different optimizer"
+            ),
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+#[derive(Serialize, Deserialize)]
+pub enum AnyOptimizerItem<A, W, S> {
+    Adam(A),
+    AdamW(W),
+    Sgd(S),
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<A: Record, W: Record, S: Record> Record for AnyOptimizer<A, W, S> {
+    type Item<P: PrecisionSettings> =
+        AnyOptimizerItem<A::Item<P>, W::Item<P>, S::Item<P>>;
+
+    fn into_item<P: PrecisionSettings>(self) -> Self::Item<P> {
+        match self {
+            Self::Adam(record) => {
+                AnyOptimizerItem::Adam(record.into_item())
+            }
+            Self::AdamW(record) => {
+                AnyOptimizerItem::AdamW(record.into_item())
+            }
+            Self::Sgd(record) => AnyOptimizerItem::Sgd(record.into_item()),
+        }
+    }
+
+    fn from_item<P: PrecisionSettings>(item: Self::Item<P>) -> Self {
+        match item {
+            AnyOptimizerItem::Adam(item) => Self::Adam(A::from_item(item)),
+            AnyOptimizerItem::AdamW(item) => {
+                Self::AdamW(W::from_item(item))
+            }
+            AnyOptimizerItem::Sgd(item) => Self::Sgd(S::from_item(item)),
+        }
+    }
+}
+
+/// Learning-rate schedule around `TrainingConfig::learning_rate`, the
+/// peak rate for every variant except `Noam`, which scales it by
+/// `model_size^-0.5` as in the original formulation.
+#[derive(Config, Debug)]
+pub enum Scheduler {
+    Constant,
+    /// Linear warmup, then cosine decay to `final_fraction` of the peak.
+    Cosine {
+        final_fraction: f64,
+    },
+    /// Multiply by `gamma` every `step_size` steps.
+    StepDecay {
+        step_size: usize,
+        gamma: f64,
+    },
+    /// Multiply by `gamma` every step.
+    Exponential {
+        gamma: f64,
+    },
+    Noam,
+    /// Warm up from `lr / 25` over the fraction `pct_start` of training,
+    /// then anneal to `lr / 1e4`.
+    OneCycle {
+        pct_start: f64,
+    },
+}
+
+/// Stateful [`Scheduler`] over a fixed number of training steps.
+#[cfg(not(target_family = "wasm"))]
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    scheduler: Scheduler,
+    learning_rate: f64,
+    warmup_steps: usize,
+    total_steps: usize,
+    model_size: usize,
+    step: usize,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Scheduler {
+    pub fn init(
+        &self,
+        learning_rate: f64,
+        warmup_steps: usize,
+        total_steps: usize,
+        model_size: usize,
+    ) -> Schedule {
+        Schedule {
+            scheduler: self.clone(),
+            learning_rate,
+            warmup_steps,
+            total_steps: total_steps.max(1),
+            model_size,
+            step: 0,
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn cosine(start: f64, end: f64, progress: f64) -> f64 {
+    let progress = progress.clamp(0., 1.);
+    end + (start - end) * (1. + (std::f64::consts::PI * progress).cos())
+        / 2.
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Schedule {
+    fn lr(&self) -> f64 {
+        let lr = self.learning_rate;
+        let (step, warmup, total) =
+            (self.step, self.warmup_steps, self.total_steps);
+        match self.scheduler {
+            Scheduler::Constant => lr,
+            Scheduler::Cosine { final_fraction } => match step < warmup {
+                true => lr * step as f64 / warmup as f64,
+                false => cosine(
+                    lr,
+                    lr * final_fraction,
+                    (step - warmup) as f64
+                        / total.saturating_sub(warmup).max(1) as f64,
+                ),
+            },
+            Scheduler::StepDecay { step_size, gamma } => {
+                lr * gamma.powi((step / step_size.max(1)) as i32)
+            }
+            Scheduler::Exponential { gamma } => {
+                lr * gamma.powi(step as i32)
+            }
+            Scheduler::Noam => {
+                let step = step.max(1) as f64;
+                lr * (self.model_size as f64).powf(-0.5)
+                    * f64::min(
+                        step.powf(-0.5),
+                        step * (warmup.max(1) as f64).powf(-1.5),
+                    )
+            }
+            Scheduler::OneCycle { pct_start } => {
+                let peak = (pct_start * total as f64).max(1.);
+                match (step as f64) < peak {
+                    true => cosine(lr / 25., lr, step as f64 / peak),
+                    false => cosine(
+                        lr,
+                        lr / 1e4,
+                        (step as f64 - peak)
+                            / (total as f64 - peak).max(1.),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl LrScheduler for Schedule {
+    type Record = usize;
+
+    fn step(&mut self) -> LearningRate {
+        self.step += 1;
+        self.lr()
+    }
+
+    fn to_record(&self) -> Self::Record {
+        self.step
+    }
+
+    fn load_record(mut self, record: Self::Record) -> Self {
+        self.step = record;
+        self
+    }
+}
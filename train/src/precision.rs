@@ -0,0 +1,162 @@
+use crate::{Architecture, TrainingConfig};
+use burn::{
+    config::Config,
+    module::Module,
+    record::{
+        BinBytesRecorder, FullPrecisionSettings, HalfPrecisionSettings,
+        PrecisionSettings, Record, Recorder,
+    },
+    tensor::{Device, backend::Backend, bf16},
+};
+use vae::{Generator, ModelFn};
+
+/// Float precision of the exported `model.bin`, recorded in `config.json`
+/// and in a header on the bytes so loaders decode it correctly.
+#[derive(Config, Debug, PartialEq, Eq)]
+pub enum Precision {
+    Full,
+    Half,
+    BFloat16,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct BFloat16PrecisionSettings;
+
+impl PrecisionSettings for BFloat16PrecisionSettings {
+    type FloatElem = bf16;
+    type IntElem = i16;
+}
+
+impl Precision {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Full => "f32",
+            Self::Half => "f16",
+            Self::BFloat16 => "bf16",
+        }
+    }
+
+    /// Header written before the serialized record.
+    fn magic(&self) -> &'static [u8; 4] {
+        match self {
+            Self::Full => b"BV32",
+            Self::Half => b"BV16",
+            Self::BFloat16 => b"BVBF",
+        }
+    }
+
+    /// The precision named by the header of `bytes`, if it has one.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        [Self::Full, Self::Half, Self::BFloat16]
+            .into_iter()
+            .find(|precision| bytes.starts_with(precision.magic()))
+    }
+
+    pub fn to_bytes<R: Record>(&self, record: R) -> Vec<u8> {
+        let record = match self {
+            Self::Full => {
+                BinBytesRecorder::<FullPrecisionSettings>::default()
+                    .record(record, ())
+            }
+            Self::Half => {
+                BinBytesRecorder::<HalfPrecisionSettings>::default()
+                    .record(record, ())
+            }
+            Self::BFloat16 => {
+                BinBytesRecorder::<BFloat16PrecisionSettings>::default()
+                    .record(record, ())
+            }
+        }
+        .expect("Record should be serialized successfully");
+        [self.magic().as_slice(), &record].concat()
+    }
+
+    /// Serializes the weights of whichever model `generator` wraps.
+    pub fn generator_to_bytes<B: Backend>(
+        &self,
+        generator: &Generator<B>,
+    ) -> Vec<u8> {
+        generator.with_model(ToBytes(self))
+    }
+
+    pub fn from_bytes<R: Record>(&self, bytes: Vec<u8>) -> R {
+        match Self::detect(&bytes) {
+            Some(stored) if stored == *self => (),
+            Some(stored) => panic!(
+                "Weights are stored in {}, not {}",
+                stored.name(),
+                self.name()
+            ),
+            None => panic!("Not an exported model"),
+        }
+        let bytes = bytes[self.magic().len()..].to_vec();
+        match self {
+            Self::Full => {
+                BinBytesRecorder::<FullPrecisionSettings>::default()
+                    .load(bytes)
+            }
+            Self::Half => {
+                BinBytesRecorder::<HalfPrecisionSettings>::default()
+                    .load(bytes)
+            }
+            Self::BFloat16 => {
+                BinBytesRecorder::<BFloat16PrecisionSettings>::default()
+                    .load(bytes)
+            }
+        }
+        .expect("Failed to load weights")
+    }
+}
+
+struct ToBytes<'a>(&'a Precision);
+
+impl<B: Backend> ModelFn<B> for ToBytes<'_> {
+    type Output = Vec<u8>;
+
+    fn call<M: Module<B>>(self, model: &M) -> Vec<u8> {
+        self.0.to_bytes(model.clone().into_record())
+    }
+}
+
+/// Decodes weights stored at `stored` precision, rounding them through
+/// `target` precision when it differs.
+fn decode<R: Record>(
+    bytes: Vec<u8>,
+    stored: &Precision,
+    target: &Precision,
+) -> R {
+    let record = stored.from_bytes(bytes);
+    match stored == target {
+        true => record,
+        false => target.from_bytes(target.to_bytes(record)),
+    }
+}
+
+/// Builds the configured generator from serialized weights.
+pub fn generator_from_bytes<B: Backend>(
+    config: &TrainingConfig,
+    bytes: Vec<u8>,
+    stored: &Precision,
+    target: &Precision,
+    device: &Device<B>,
+) -> Generator<B> {
+    match &config.architecture {
+        Architecture::Gaussian => config
+            .model
+            .init_with::<B>(decode(bytes, stored, target))
+            .to_device(device)
+            .into(),
+        Architecture::VectorQuantized(vq) => vq
+            .init_with::<B>(decode(bytes, stored, target))
+            .to_device(device)
+            .into(),
+        Architecture::Flow(flow) => flow
+            .init_with::<B>(decode(bytes, stored, target))
+            .to_device(device)
+            .into(),
+        Architecture::Diffusion(ddpm) => ddpm
+            .init_with::<B>(decode(bytes, stored, target))
+            .to_device(device)
+            .into(),
+    }
+}
@@ -0,0 +1,279 @@
+//! Post-training int8 weight quantization. Every 2-D float parameter is
+//! stored as int8 with one f32 scale per column: the output channel of a
+//! `Linear` weight, the embedding dimension of an `Embedding` table or
+//! the VQ codebook. Biases, norms and other parameters stay f32. Loading
+//! dequantizes into a freshly initialised model, matching parameters by
+//! visiting order.
+use crate::{Architecture, TrainingConfig};
+use burn::{
+    module::{Module, ModuleMapper, ModuleVisitor, ParamId},
+    tensor::{Data, Device, Shape, Tensor, backend::Backend},
+};
+use vae::{Generator, ModelFn};
+
+const MAGIC: &[u8; 4] = b"BVQ8";
+
+enum Stored {
+    Float(Vec<f32>),
+    Int8 { scales: Vec<f32>, values: Vec<i8> },
+}
+
+struct Param {
+    shape: Vec<usize>,
+    stored: Stored,
+}
+
+impl Param {
+    fn quantize(shape: Vec<usize>, values: Vec<f32>) -> Self {
+        let stored = match shape[..] {
+            [_, channels] => {
+                let mut scales = vec![0f32; channels];
+                values.iter().enumerate().for_each(|(i, x)| {
+                    let scale = &mut scales[i % channels];
+                    *scale = scale.max(x.abs() / 127.);
+                });
+                let values = values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| match scales[i % channels] {
+                        scale if scale > 0. => {
+                            (x / scale).round().clamp(-127., 127.) as i8
+                        }
+                        _ => 0,
+                    })
+                    .collect();
+                Stored::Int8 { scales, values }
+            }
+            _ => Stored::Float(values),
+        };
+        Self { shape, stored }
+    }
+
+    fn dequantize(&self) -> Vec<f32> {
+        match &self.stored {
+            Stored::Float(values) => values.clone(),
+            Stored::Int8 { scales, values } => values
+                .iter()
+                .enumerate()
+                .map(|(i, q)| *q as f32 * scales[i % scales.len()])
+                .collect(),
+        }
+    }
+}
+
+struct Quantize(Vec<Param>);
+
+impl<B: Backend> ModuleVisitor<B> for Quantize {
+    fn visit_float<const D: usize>(
+        &mut self,
+        _id: &ParamId,
+        tensor: &Tensor<B, D>,
+    ) {
+        let data = tensor.to_data().convert::<f32>();
+        self.0
+            .push(Param::quantize(data.shape.dims.to_vec(), data.value));
+    }
+}
+
+struct Dequantize(std::vec::IntoIter<Param>);
+
+impl<B: Backend> ModuleMapper<B> for Dequantize {
+    fn map_float<const D: usize>(
+        &mut self,
+        _id: &ParamId,
+        tensor: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        let param =
+            self.0.next().expect("Quantized model is missing weights");
+        let dims = tensor.shape().dims;
+        assert_eq!(
+            param.shape, dims,
+            "Quantized weights do not match model"
+        );
+        let data = Data::new(param.dequantize(), Shape::new(dims));
+        Tensor::from_data(data.convert()).to_device(&tensor.device())
+    }
+}
+
+/// Serializes a module's weights in int8.
+pub fn quantize<B: Backend, M: Module<B>>(module: &M) -> Vec<u8> {
+    let mut params = Quantize(Vec::new());
+    module.visit(&mut params);
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend((params.0.len() as u32).to_le_bytes());
+    params.0.iter().for_each(|param| {
+        bytes.extend((param.shape.len() as u32).to_le_bytes());
+        param
+            .shape
+            .iter()
+            .for_each(|dim| bytes.extend((*dim as u32).to_le_bytes()));
+        match &param.stored {
+            Stored::Float(values) => {
+                bytes.push(0);
+                values.iter().for_each(|x| bytes.extend(x.to_le_bytes()));
+            }
+            Stored::Int8 { scales, values } => {
+                bytes.push(1);
+                scales.iter().for_each(|x| bytes.extend(x.to_le_bytes()));
+                bytes.extend(values.iter().map(|q| *q as u8));
+            }
+        }
+    });
+    bytes
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> &[u8] {
+        assert!(self.0.len() >= n, "Quantized weights are truncated");
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        head
+    }
+
+    fn u32(&mut self) -> usize {
+        u32::from_le_bytes(self.take(4).try_into().unwrap()) as usize
+    }
+
+    fn f32s(&mut self, n: usize) -> Vec<f32> {
+        self.take(4 * n)
+            .chunks_exact(4)
+            .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+            .collect()
+    }
+}
+
+fn parse(bytes: &[u8]) -> Vec<Param> {
+    let mut reader = Reader(bytes);
+    assert_eq!(reader.take(4), MAGIC, "Not an int8 quantized model");
+    (0..reader.u32())
+        .map(|_| {
+            let rank = reader.u32();
+            let shape =
+                (0..rank).map(|_| reader.u32()).collect::<Vec<_>>();
+            let n = shape.iter().product::<usize>();
+            let stored = match reader.take(1)[0] {
+                0 => Stored::Float(reader.f32s(n)),
+                _ => Stored::Int8 {
+                    scales: reader.f32s(shape[1]),
+                    values: reader
+                        .take(n)
+                        .iter()
+                        .map(|q| *q as i8)
+                        .collect(),
+                },
+            };
+            Param { shape, stored }
+        })
+        .collect()
+}
+
+/// Loads int8 weights into `module`, which must have the same structure
+/// as the quantized one.
+pub fn dequantize<B: Backend, M: Module<B>>(module: M, bytes: &[u8]) -> M {
+    let mut params = Dequantize(parse(bytes).into_iter());
+    let module = module.map(&mut params);
+    assert!(
+        params.0.next().is_none(),
+        "Quantized model has extra weights"
+    );
+    module
+}
+
+/// Serializes a generator's weights in int8.
+pub fn quantize_generator<B: Backend>(
+    generator: &Generator<B>,
+) -> Vec<u8> {
+    generator.with_model(Int8)
+}
+
+struct Int8;
+
+impl<B: Backend> ModelFn<B> for Int8 {
+    type Output = Vec<u8>;
+
+    fn call<M: Module<B>>(self, model: &M) -> Vec<u8> {
+        quantize(model)
+    }
+}
+
+/// Builds the configured generator from int8 weights.
+pub fn generator_from_quantized<B: Backend>(
+    config: &TrainingConfig,
+    bytes: &[u8],
+    device: &Device<B>,
+) -> Generator<B> {
+    match &config.architecture {
+        Architecture::Gaussian => {
+            dequantize(config.model.init::<B>().to_device(device), bytes)
+                .into()
+        }
+        Architecture::VectorQuantized(vq) => {
+            dequantize(vq.init::<B>().to_device(device), bytes).into()
+        }
+        Architecture::Flow(flow) => {
+            dequantize(flow.init::<B>().to_device(device), bytes).into()
+        }
+        Architecture::Diffusion(ddpm) => {
+            dequantize(ddpm.init::<B>().to_device(device), bytes).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::{backend::NdArray, nn::LinearConfig};
+    use dataset::{INPUT_DIM, LABEL_DIM};
+    use vae::{
+        DecoderConfig, EncoderConfig, MLPBlockConfig, ModelConfig,
+        quality::{chamfer, mmd},
+    };
+
+    type TestBackend = NdArray<f32>;
+
+    #[test]
+    fn quantized_samples_stay_close_to_full_precision() {
+        const N: usize = 256;
+        let device = Default::default();
+        let model = ModelConfig::new(
+            EncoderConfig::new(
+                MLPBlockConfig::new(2, 64, INPUT_DIM, 16),
+                LinearConfig::new(16, 2),
+                LinearConfig::new(16, 2),
+            ),
+            DecoderConfig::new(
+                MLPBlockConfig::new(2, 64, 2 + LABEL_DIM, 16),
+                LinearConfig::new(16, INPUT_DIM),
+            ),
+        )
+        .init::<TestBackend>();
+        let bytes = quantize(&model);
+        let full = Generator::from(model.clone());
+        let quantized = Generator::from(dequantize(model, &bytes));
+
+        let sample = |generator: &Generator<TestBackend>, seed| {
+            TestBackend::seed(seed);
+            [-1., 0., 1.]
+                .into_iter()
+                .flat_map(|t| generator.generate(t, N, &device))
+                .collect::<Vec<_>>()
+        };
+        let (full, quantized, resampled) =
+            (sample(&full, 0), sample(&quantized, 0), sample(&full, 1));
+
+        // Rounding the weights should move samples far less than drawing
+        // them again does.
+        assert!(
+            chamfer(&quantized, &full) < chamfer(&resampled, &full) / 2.,
+            "quantized samples drift from full precision by Chamfer distance"
+        );
+        assert!(
+            mmd(&quantized, &full, None)
+                < mmd(&resampled, &full, None) / 10.,
+            "quantized samples drift from full precision by MMD"
+        );
+    }
+}
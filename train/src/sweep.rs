@@ -0,0 +1,343 @@
+//! Hyperparameter sweeps over [`TrainingConfig`]. A JSON or TOML spec
+//! lists parameters by dotted path into the config (`learning_rate`,
+//! `model.kl_weight`, `model.encoder.block_config.hidden_dim`, ...);
+//! `latent_dim` also resizes the encoder heads and decoder input.
+//! Each trial runs as `<exe> trial <dir>` in its own subdirectory and is
+//! ranked by the best-epoch metrics in its `summary.json`.
+use crate::TrainingConfig;
+use burn::config::Config;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    #[default]
+    Grid,
+    Random,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Lowest,
+    Highest,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Parameter {
+    Values(Vec<Value>),
+    Range {
+        min: f64,
+        max: f64,
+        #[serde(default)]
+        log: bool,
+        #[serde(default)]
+        integer: bool,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct SweepSpec {
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Number of trials for random search.
+    #[serde(default = "SweepSpec::default_trials")]
+    pub trials: usize,
+    #[serde(default)]
+    pub seed: u64,
+    /// Number of trials run at once.
+    #[serde(default = "SweepSpec::default_parallel")]
+    pub parallel: usize,
+    /// Validation metric to rank by, as named on the dashboard.
+    #[serde(default = "SweepSpec::default_metric")]
+    pub metric: String,
+    #[serde(default)]
+    pub direction: Direction,
+    pub parameters: BTreeMap<String, Parameter>,
+}
+
+impl SweepSpec {
+    fn default_trials() -> usize {
+        10
+    }
+
+    fn default_parallel() -> usize {
+        1
+    }
+
+    fn default_metric() -> String {
+        "Loss".to_string()
+    }
+
+    pub fn load(path: &str) -> Self {
+        let text =
+            std::fs::read_to_string(path).expect("Sweep spec not found");
+        match path.ends_with(".toml") {
+            true => {
+                toml::from_str(&text).expect("Invalid TOML sweep spec")
+            }
+            false => serde_json::from_str(&text)
+                .expect("Invalid JSON sweep spec"),
+        }
+    }
+
+    /// Parameter assignments, one per trial.
+    pub fn trials(&self) -> Vec<BTreeMap<String, Value>> {
+        match self.strategy {
+            Strategy::Grid => self.parameters.iter().fold(
+                vec![BTreeMap::new()],
+                |trials, (name, parameter)| {
+                    let Parameter::Values(values) = parameter else {
+                        panic!(
+                            "Grid search needs a list of values for {name}"
+                        )
+                    };
+                    trials
+                        .iter()
+                        .flat_map(|trial| {
+                            values.iter().map(move |value| {
+                                let mut trial = trial.clone();
+                                trial.insert(name.clone(), value.clone());
+                                trial
+                            })
+                        })
+                        .collect()
+                },
+            ),
+            Strategy::Random => {
+                let mut rng = StdRng::seed_from_u64(self.seed);
+                (0..self.trials)
+                    .map(|_| {
+                        self.parameters
+                            .iter()
+                            .map(|(name, parameter)| {
+                                (name.clone(), sample(parameter, &mut rng))
+                            })
+                            .collect()
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+fn sample(parameter: &Parameter, rng: &mut StdRng) -> Value {
+    match parameter {
+        Parameter::Values(values) => {
+            values[rng.gen_range(0..values.len())].clone()
+        }
+        Parameter::Range {
+            min,
+            max,
+            log,
+            integer,
+        } => {
+            let x = match log {
+                true => rng.gen_range(min.ln()..=max.ln()).exp(),
+                false => rng.gen_range(*min..=*max),
+            };
+            match integer {
+                true => json!(x.round() as i64),
+                false => json!(x),
+            }
+        }
+    }
+}
+
+fn set_path(config: &mut Value, path: &str, value: Value) {
+    let target = path.split('.').fold(config, |node, key| {
+        node.get_mut(key)
+            .unwrap_or_else(|| panic!("Unknown config path {path}"))
+    });
+    *target = value;
+}
+
+fn set_latent_dim(config: &mut Value, dim: &Value) {
+    let old = config["model"]["latent_dim"].as_u64().unwrap_or(0);
+    let new = dim.as_u64().expect("latent_dim must be an integer");
+    let decoder_input =
+        &mut config["model"]["decoder"]["block_config"]["input_dim"];
+    *decoder_input =
+        json!(decoder_input.as_u64().unwrap_or(old) - old + new);
+    config["model"]["latent_dim"] = json!(new);
+    config["model"]["encoder"]["fc_mu"]["d_output"] = json!(new);
+    config["model"]["encoder"]["fc_logvar"]["d_output"] = json!(new);
+}
+
+/// Derives a trial's config by overriding `base` with `params`.
+pub fn derive_config(
+    base: &TrainingConfig,
+    params: &BTreeMap<String, Value>,
+) -> TrainingConfig {
+    let mut config =
+        serde_json::to_value(base).expect("to serialize base config");
+    params.iter().for_each(|(path, value)| match path.as_str() {
+        "latent_dim" | "model.latent_dim" => {
+            set_latent_dim(&mut config, value)
+        }
+        _ => set_path(&mut config, path, value.clone()),
+    });
+    serde_json::from_value(config).expect("Invalid trial config")
+}
+
+struct Trial {
+    dir: String,
+    params: BTreeMap<String, Value>,
+    success: bool,
+    /// How the trial process ended, e.g. `exit status: 101`.
+    status: Option<String>,
+}
+
+fn spawn(dir: &str) -> Child {
+    let log = File::create(format!("{dir}/stdout.log"))
+        .expect("to create trial log");
+    Command::new(std::env::current_exe().expect("current executable"))
+        .args(["trial", dir])
+        .stdout(log.try_clone().expect("to clone trial log"))
+        .stderr(log)
+        .stdin(Stdio::null())
+        .spawn()
+        .expect("to start trial")
+}
+
+fn best_metric(dir: &str, metric: &str) -> Option<f64> {
+    let summary = std::fs::read_to_string(format!("{dir}/summary.json"))
+        .ok()
+        .and_then(|text| serde_json::from_str::<Value>(&text).ok())?;
+    let best = &summary["best"];
+    best["valid"][metric]
+        .as_f64()
+        .or_else(|| best["train"][metric].as_f64())
+}
+
+/// Runs every trial of `spec` under `dir` and writes `leaderboard.json`
+/// and `leaderboard.csv` there.
+pub fn sweep(spec: &str, dir: &str, base: &TrainingConfig) {
+    let spec = SweepSpec::load(spec);
+    let mut trials = spec
+        .trials()
+        .into_iter()
+        .enumerate()
+        .map(|(i, params)| {
+            let trial_dir = format!("{dir}/trial_{i:03}");
+            std::fs::create_dir_all(&trial_dir).ok();
+            derive_config(base, &params)
+                .save(format!("{trial_dir}/config.json"))
+                .expect(
+                    "Trial configuration should be saved successfully",
+                );
+            Trial {
+                dir: trial_dir,
+                params,
+                success: false,
+                status: None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut queue = (0..trials.len()).rev().collect::<Vec<_>>();
+    let mut running = Vec::<(usize, Child)>::new();
+    while !queue.is_empty() || !running.is_empty() {
+        while running.len() < spec.parallel.max(1) {
+            let Some(i) = queue.pop() else { break };
+            println!("Starting {}", trials[i].dir);
+            running.push((i, spawn(&trials[i].dir)));
+        }
+        running.retain_mut(|(i, child)| match child.try_wait() {
+            Ok(None) => true,
+            Ok(Some(status)) => {
+                trials[*i].success = status.success();
+                trials[*i].status = Some(status.to_string());
+                println!("Finished {} ({status})", trials[*i].dir);
+                false
+            }
+            Err(error) => {
+                trials[*i].status = Some(error.to_string());
+                println!(
+                    "Could not wait for {} ({error})",
+                    trials[*i].dir
+                );
+                false
+            }
+        });
+        std::thread::sleep(Duration::from_millis(200));
+    }
+
+    leaderboard(dir, &spec, &trials);
+}
+
+fn leaderboard(dir: &str, spec: &SweepSpec, trials: &[Trial]) {
+    let mut ranked = trials
+        .iter()
+        .map(|trial| (trial, best_metric(&trial.dir, &spec.metric)))
+        .collect::<Vec<_>>();
+    ranked.sort_by(|(_, a), (_, b)| match (a, b) {
+        (Some(a), Some(b)) => match spec.direction {
+            Direction::Lowest => a.total_cmp(b),
+            Direction::Highest => b.total_cmp(a),
+        },
+        _ => b.is_some().cmp(&a.is_some()),
+    });
+
+    let entries = ranked
+        .iter()
+        .enumerate()
+        .map(|(rank, (trial, value))| {
+            json!({
+                "rank": rank + 1,
+                "dir": trial.dir,
+                "success": trial.success,
+                "status": trial.status,
+                "metric": spec.metric,
+                "value": value,
+                "params": trial.params,
+            })
+        })
+        .collect::<Vec<_>>();
+    std::fs::write(
+        format!("{dir}/leaderboard.json"),
+        serde_json::to_string_pretty(&entries)
+            .expect("to serialize leaderboard"),
+    )
+    .expect("to write leaderboard.json");
+
+    let mut csv = "rank,dir,success,status,value,params\n".to_string();
+    println!(
+        "{:>4}  {:<24} {:<22} {:>12}  params",
+        "rank", "trial", "status", spec.metric
+    );
+    ranked
+        .iter()
+        .enumerate()
+        .for_each(|(rank, (trial, value))| {
+            let value =
+                value.map_or("n/a".to_string(), |v| format!("{v:.6}"));
+            let params = serde_json::to_string(&trial.params)
+                .expect("to serialize params");
+            let status = trial.status.as_deref().unwrap_or("not run");
+            csv += &format!(
+                "{},{},{},\"{status}\",{value},\"{}\"\n",
+                rank + 1,
+                trial.dir,
+                trial.success,
+                params.replace('"', "\"\""),
+            );
+            println!(
+                "{:>4}  {:<24} {status:<22} {value:>12}  {params}",
+                rank + 1,
+                trial.dir
+            );
+        });
+    std::fs::write(format!("{dir}/leaderboard.csv"), csv)
+        .expect("to write leaderboard.csv");
+}
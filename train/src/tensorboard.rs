@@ -0,0 +1,259 @@
+//! Minimal TensorBoard event-file writer: TFRecord framing around
+//! hand-encoded `Event` protobufs, covering scalars and histograms.
+use burn::{
+    tensor::{ElementConversion, backend::Backend},
+    train::metric::{Metric, MetricEntry, MetricMetadata},
+};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    marker::PhantomData,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use vae::metric::ActiveUnitsInput;
+
+const HISTOGRAM_BINS: usize = 30;
+
+fn crc32c(data: &[u8]) -> u32 {
+    const POLY: u32 = 0x82f6_3b78;
+    let mut crc = !0u32;
+    data.iter().for_each(|byte| {
+        crc ^= *byte as u32;
+        (0..8).for_each(|_| {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ POLY,
+                _ => crc >> 1,
+            };
+        });
+    });
+    !crc
+}
+
+fn masked_crc(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+#[derive(Default)]
+struct Proto(Vec<u8>);
+
+impl Proto {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.varint(field << 3 | wire_type);
+    }
+
+    fn int(mut self, field: u64, value: u64) -> Self {
+        self.key(field, 0);
+        self.varint(value);
+        self
+    }
+
+    fn double(mut self, field: u64, value: f64) -> Self {
+        self.key(field, 1);
+        self.0.extend(value.to_le_bytes());
+        self
+    }
+
+    fn float(mut self, field: u64, value: f32) -> Self {
+        self.key(field, 5);
+        self.0.extend(value.to_le_bytes());
+        self
+    }
+
+    fn bytes(mut self, field: u64, value: &[u8]) -> Self {
+        self.key(field, 2);
+        self.varint(value.len() as u64);
+        self.0.extend(value);
+        self
+    }
+
+    fn doubles(self, field: u64, values: &[f64]) -> Self {
+        let packed = values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect::<Vec<_>>();
+        self.bytes(field, &packed)
+    }
+}
+
+/// Appends events to `events.out.tfevents.*` in a run directory.
+pub struct EventWriter {
+    file: BufWriter<File>,
+}
+
+impl EventWriter {
+    pub fn new(dir: &str, suffix: &str) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let host = std::env::var("HOSTNAME")
+            .unwrap_or_else(|_| "localhost".to_string());
+        let path =
+            format!("{dir}/events.out.tfevents.{secs}.{host}{suffix}");
+
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path)?),
+        };
+        writer
+            .write_event(0, Proto::default().bytes(3, b"brain.Event:2"))?;
+        Ok(writer)
+    }
+
+    fn write_event(
+        &mut self,
+        step: usize,
+        body: Proto,
+    ) -> std::io::Result<()> {
+        let wall_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0., |time| time.as_secs_f64());
+        let mut event =
+            Proto::default().double(1, wall_time).int(2, step as u64);
+        event.0.extend(body.0);
+
+        let len = (event.0.len() as u64).to_le_bytes();
+        self.file.write_all(&len)?;
+        self.file.write_all(&masked_crc(&len).to_le_bytes())?;
+        self.file.write_all(&event.0)?;
+        self.file.write_all(&masked_crc(&event.0).to_le_bytes())?;
+        self.file.flush()
+    }
+
+    fn write_summary(
+        &mut self,
+        step: usize,
+        value: Proto,
+    ) -> std::io::Result<()> {
+        let summary = Proto::default().bytes(1, &value.0);
+        self.write_event(step, Proto::default().bytes(5, &summary.0))
+    }
+
+    pub fn scalar(
+        &mut self,
+        tag: &str,
+        step: usize,
+        value: f64,
+    ) -> std::io::Result<()> {
+        let value = Proto::default()
+            .bytes(1, tag.as_bytes())
+            .float(2, value as f32);
+        self.write_summary(step, value)
+    }
+
+    pub fn histogram(
+        &mut self,
+        tag: &str,
+        step: usize,
+        values: &[f32],
+    ) -> std::io::Result<()> {
+        let values = values
+            .iter()
+            .map(|value| *value as f64)
+            .filter(|value| value.is_finite())
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return Ok(());
+        }
+
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let width = ((max - min) / HISTOGRAM_BINS as f64).max(1e-12);
+        let mut counts = vec![0.; HISTOGRAM_BINS];
+        values.iter().for_each(|value| {
+            let bin =
+                (((value - min) / width) as usize).min(HISTOGRAM_BINS - 1);
+            counts[bin] += 1.;
+        });
+        let limits = (1..=HISTOGRAM_BINS)
+            .map(|i| min + width * i as f64)
+            .collect::<Vec<_>>();
+
+        let histogram = Proto::default()
+            .double(1, min)
+            .double(2, max)
+            .double(3, values.len() as f64)
+            .double(4, values.iter().sum())
+            .double(5, values.iter().map(|value| value * value).sum())
+            .doubles(6, &limits)
+            .doubles(7, &counts);
+        let value = Proto::default()
+            .bytes(1, tag.as_bytes())
+            .bytes(5, &histogram.0);
+        self.write_summary(step, value)
+    }
+}
+
+/// Writes a histogram of each latent dimension's posterior mean over the
+/// validation set at the end of every epoch.
+pub struct LatentHistogramMetric<B: Backend> {
+    writer: EventWriter,
+    epoch: usize,
+    dims: Vec<Vec<f32>>,
+    _b: PhantomData<B>,
+}
+
+impl<B: Backend> LatentHistogramMetric<B> {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            writer: EventWriter::new(
+                &format!("{dir}/tensorboard/valid"),
+                ".latent",
+            )
+            .expect("to create tensorboard event file"),
+            epoch: 0,
+            dims: Vec::new(),
+            _b: PhantomData,
+        }
+    }
+}
+
+impl<B: Backend> Metric for LatentHistogramMetric<B> {
+    const NAME: &'static str = "Latent Histogram";
+    type Input = ActiveUnitsInput<B>;
+
+    fn update(
+        &mut self,
+        item: &ActiveUnitsInput<B>,
+        metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let [_, latent_dim] = item.latent_mean().dims();
+        let values = item.latent_mean().clone().into_data().value;
+        self.dims.resize(latent_dim, Vec::new());
+        values.chunks(latent_dim).for_each(|row| {
+            row.iter().zip(self.dims.iter_mut()).for_each(|(x, dim)| {
+                dim.push(x.elem::<f32>());
+            });
+        });
+        self.epoch = metadata.epoch;
+
+        let n = self.dims.first().map_or(0, Vec::len);
+        MetricEntry::new(
+            Self::NAME.to_string(),
+            format!("{}: {n} points", Self::NAME),
+            n.to_string(),
+        )
+    }
+
+    fn clear(&mut self) {
+        std::mem::take(&mut self.dims).iter().enumerate().for_each(
+            |(i, values)| {
+                self.writer
+                    .histogram(
+                        &format!("latent_mean/{i}"),
+                        self.epoch,
+                        values,
+                    )
+                    .expect("to write tensorboard event");
+            },
+        );
+    }
+}
@@ -1,30 +1,88 @@
-use burn::{config::Config, module::Module, optim::AdamWConfig};
+use burn::{
+    config::Config, grad_clipping::GradientClippingConfig, module::Module,
+};
 #[cfg(not(target_family = "wasm"))]
 use burn::{
     data::dataloader::DataLoaderBuilder,
-    lr_scheduler::noam::NoamLrSchedulerConfig,
+    module::AutodiffModule,
     record::CompactRecorder,
-    tensor::backend::AutodiffBackend,
+    tensor::backend::{AutodiffBackend, Backend},
     train::{
         LearnerBuilder, MetricEarlyStoppingStrategy, StoppingCondition,
         metric::{
-            LearningRateMetric, LossMetric,
+            Adaptor, LearningRateMetric, LossInput, LossMetric,
             store::{Aggregate, Direction, Split},
         },
     },
 };
-use dataset::{SpiralBatcher, SpiralDataset};
-use vae::ModelConfig;
+use dataset::{DataSource, SpiralBatcher, SpiralDataset};
+#[cfg(not(target_family = "wasm"))]
+use std::{
+    fmt::Display,
+    io::IsTerminal,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use vae::{DiffusionConfig, FlowConfig, ModelConfig, VQVAEConfig};
+#[cfg(not(target_family = "wasm"))]
+use vae::{NonFiniteGuard, NonFiniteLoss};
+
+use crate::ema::EmaConfig;
+#[cfg(not(target_family = "wasm"))]
+use crate::ema::{EmaOptimizer, EmaVAE};
+#[cfg(not(target_family = "wasm"))]
+use crate::optim::Schedule;
+use crate::optim::{Optimizer, Scheduler};
+use crate::precision::Precision;
+#[cfg(not(target_family = "wasm"))]
+use crate::quantize::quantize;
+
+#[cfg(not(target_family = "wasm"))]
+use crate::logger::{LogRenderer, loggers};
+#[cfg(not(target_family = "wasm"))]
+use crate::metric::{
+    CpuUsageMetric, GpuUsageMetric, MemoryUsageMetric, ThroughputMetric,
+};
+#[cfg(not(target_family = "wasm"))]
+use crate::tensorboard::LatentHistogramMetric;
+#[cfg(not(target_family = "wasm"))]
+use vae::metric::{
+    ActiveUnitsMetric, ChamferMetric, CodebookPerplexityMetric,
+    CodebookUsageMetric, ConditionalFidelityMetric, KLLossMetric,
+    LabelRMSEMetric, LabeledLossMetric, MMDMetric, NonFiniteStopMetric,
+    ReconstructionLossMetric, SlicedWassersteinMetric,
+    UnlabeledLossMetric,
+};
+#[cfg(not(target_family = "wasm"))]
+use vae::metric::{DiffusionOutput, FlowOutput, VAEOutput, VQVAEOutput};
 
 #[cfg(not(target_family = "wasm"))]
-use crate::metric::NvidiaUtilMetric;
+const TRAIN_SIZE: usize = 10_000;
 #[cfg(not(target_family = "wasm"))]
-use vae::metric::{KLLossMetric, ReconstructionLossMetric};
+const VALID_SIZE: usize = 1_000;
+
+#[derive(Config, Debug)]
+pub enum Architecture {
+    Gaussian,
+    VectorQuantized(VQVAEConfig),
+    Flow(FlowConfig),
+    Diffusion(DiffusionConfig),
+}
 
 #[derive(Config)]
 pub struct TrainingConfig {
     pub model: ModelConfig,
-    pub optimizer: AdamWConfig,
+    pub optimizer: Optimizer,
+    #[config(default = "Scheduler::Noam")]
+    pub scheduler: Scheduler,
+    #[config(default = "Architecture::Gaussian")]
+    pub architecture: Architecture,
+    #[config(default = "DataSource::SwissRoll")]
+    pub data: DataSource,
+    #[config(default = 1.)]
+    pub label_fraction: f64,
     #[config(default = 10)]
     pub num_epochs: usize,
     #[config(default = 64)]
@@ -39,6 +97,49 @@ pub struct TrainingConfig {
     pub warmup_steps: usize,
     #[config(default = 10)]
     pub early_stop_patience: usize,
+    /// Also write TensorBoard event files under `tensorboard/`.
+    #[config(default = false)]
+    pub tensorboard: bool,
+    /// With `tensorboard`, add per-dimension latent mean histograms.
+    #[config(default = false)]
+    pub latent_histogram: bool,
+    /// Clips gradients by value or norm, for any optimizer.
+    pub grad_clipping: Option<GradientClippingConfig>,
+    /// Tracks an EMA of the Gaussian VAE's weights, validated on and saved
+    /// as `model_ema`.
+    pub ema: Option<EmaConfig>,
+    /// Precision of the exported `model.bin`. Below full precision, the
+    /// full weights are also kept as `model.full.bin`.
+    #[config(default = "Precision::Full")]
+    pub export_precision: Precision,
+    /// Also export int8 weights as `model.q8.bin`.
+    #[config(default = false)]
+    pub quantize: bool,
+}
+
+impl TrainingConfig {
+    /// Partially labeled data is only supported where missing labels are
+    /// inferred, rather than silently trained as label 0.
+    fn validate(&self) {
+        let infers_labels =
+            matches!(self.architecture, Architecture::Gaussian)
+                && self.model.infers_labels();
+        assert!(
+            self.label_fraction >= 1. || infers_labels,
+            "label_fraction < 1 needs a Gaussian VAE with a label_head and continuous conditioning"
+        );
+    }
+
+    fn model_size(&self) -> usize {
+        match &self.architecture {
+            Architecture::Gaussian => {
+                self.model.encoder.block_config.model_size()
+            }
+            Architecture::VectorQuantized(vq) => vq.encoder.model_size(),
+            Architecture::Flow(flow) => flow.block_config.model_size(),
+            Architecture::Diffusion(ddpm) => ddpm.denoiser.model_size(),
+        }
+    }
 }
 
 #[cfg(not(target_family = "wasm"))]
@@ -46,14 +147,55 @@ pub fn train<B: AutodiffBackend>(
     artifact_dir: &str,
     config: &TrainingConfig,
     device: &B::Device,
-) {
+) -> Result<(), NonFiniteLoss> {
+    config.validate();
     std::fs::create_dir_all(artifact_dir).ok();
     config
         .save(format!("{artifact_dir}/config.json"))
         .expect("Configuration should be saved successfully");
 
-    B::seed(config.seed);
+    let rollbacks = match (&config.architecture, &config.model.non_finite)
+    {
+        (Architecture::Gaussian, NonFiniteGuard::Rollback(n)) => *n,
+        _ => 0,
+    };
+    let mut checkpoint = None;
+    for attempt in 0..rollbacks {
+        B::seed(config.seed + attempt as u64);
+        match run::<B>(artifact_dir, config, device, checkpoint) {
+            Ok(()) => return Ok(()),
+            Err(NonFiniteLoss) => {
+                checkpoint = last_checkpoint(artifact_dir);
+                log::warn!(
+                    "Non-finite loss, rolling back to checkpoint {checkpoint:?} ({}/{rollbacks})",
+                    attempt + 1
+                );
+            }
+        }
+    }
+    B::seed(config.seed + rollbacks as u64);
+    run::<B>(artifact_dir, config, device, checkpoint)
+}
 
+/// Latest epoch with a saved checkpoint, if any.
+#[cfg(not(target_family = "wasm"))]
+fn last_checkpoint(artifact_dir: &str) -> Option<usize> {
+    std::fs::read_dir(format!("{artifact_dir}/checkpoint"))
+        .ok()?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            name.strip_prefix("model-")?.split('.').next()?.parse().ok()
+        })
+        .max()
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn run<B: AutodiffBackend>(
+    artifact_dir: &str,
+    config: &TrainingConfig,
+    device: &B::Device,
+    checkpoint: Option<usize>,
+) -> Result<(), NonFiniteLoss> {
     let train_batcher = SpiralBatcher::<B>::new(device.clone());
     let valid_batcher =
         SpiralBatcher::<B::InnerBackend>::new(device.clone());
@@ -61,57 +203,277 @@ pub fn train<B: AutodiffBackend>(
     let train_loader = DataLoaderBuilder::new(train_batcher)
         .batch_size(config.batch_size)
         .num_workers(config.num_workers)
-        .build(SpiralDataset::new(10_000));
+        .build(
+            SpiralDataset::with_source(TRAIN_SIZE, config.data.clone())
+                .with_label_fraction(config.label_fraction),
+        );
     let valid_loader = DataLoaderBuilder::new(valid_batcher)
         .batch_size(config.batch_size)
         .num_workers(config.num_workers)
-        .build(SpiralDataset::new(1_000));
+        .build(
+            SpiralDataset::with_source(VALID_SIZE, config.data.clone())
+                .with_label_fraction(config.label_fraction),
+        );
+
+    let scheduler = config.scheduler.init(
+        config.learning_rate,
+        config.warmup_steps,
+        config.num_epochs * TRAIN_SIZE.div_ceil(config.batch_size),
+        config.model_size(),
+    );
+    let optimizer =
+        config.optimizer.with_grad_clipping(&config.grad_clipping);
 
-    let scheduler = NoamLrSchedulerConfig::new(config.learning_rate)
-        .with_warmup_steps(config.warmup_steps)
-        .with_model_size(config.model.encoder.block_config.hidden_dim)
-        .init();
+    // Set by the non-finite guard when it stops training, in which case
+    // the interrupted model is not saved.
+    let stopped = Arc::new(AtomicBool::new(false));
+    match &config.architecture {
+        Architecture::Gaussian => match &config.ema {
+            None => {
+                let trained = gaussian_learner(
+                    artifact_dir,
+                    config,
+                    device,
+                    checkpoint,
+                    &stopped,
+                )
+                .build(
+                    config.model.init::<B>(),
+                    optimizer.init(),
+                    scheduler,
+                )
+                .fit(train_loader, valid_loader);
+                if stopped.load(Ordering::Relaxed) {
+                    return Err(NonFiniteLoss);
+                }
+                save::<B, _>(trained, artifact_dir, "model", config);
+            }
+            Some(ema) => {
+                let trained = gaussian_learner(
+                    artifact_dir,
+                    config,
+                    device,
+                    checkpoint,
+                    &stopped,
+                )
+                .build(
+                    EmaVAE::new(config.model.init::<B>()),
+                    EmaOptimizer::new(optimizer.init(), ema),
+                    scheduler,
+                )
+                .fit(train_loader, valid_loader);
+                if stopped.load(Ordering::Relaxed) {
+                    return Err(NonFiniteLoss);
+                }
+                save::<B, _>(trained.model, artifact_dir, "model", config);
+                save::<B, _>(
+                    trained.ema,
+                    artifact_dir,
+                    "model_ema",
+                    config,
+                );
+            }
+        },
+        Architecture::VectorQuantized(vq) => {
+            let trained =
+                learner::<
+                    B,
+                    VQVAEOutput<B>,
+                    VQVAEOutput<B::InnerBackend>,
+                    _,
+                    _,
+                >(artifact_dir, config, device, checkpoint)
+                .metric_train_numeric(ReconstructionLossMetric::new())
+                .metric_valid_numeric(ReconstructionLossMetric::new())
+                .metric_train_numeric(CodebookPerplexityMetric::new())
+                .metric_valid_numeric(CodebookPerplexityMetric::new())
+                .metric_train_numeric(CodebookUsageMetric::new())
+                .metric_valid_numeric(CodebookUsageMetric::new())
+                .build(vq.init::<B>(), optimizer.init(), scheduler)
+                .fit(train_loader, valid_loader);
+            save::<B, _>(trained, artifact_dir, "model", config);
+        }
+        Architecture::Flow(flow) => {
+            let trained =
+                learner::<
+                    B,
+                    FlowOutput<B>,
+                    FlowOutput<B::InnerBackend>,
+                    _,
+                    _,
+                >(artifact_dir, config, device, checkpoint)
+                .build(flow.init::<B>(), optimizer.init(), scheduler)
+                .fit(train_loader, valid_loader);
+            save::<B, _>(trained, artifact_dir, "model", config);
+        }
+        Architecture::Diffusion(ddpm) => {
+            let trained =
+                learner::<
+                    B,
+                    DiffusionOutput<B>,
+                    DiffusionOutput<B::InnerBackend>,
+                    _,
+                    _,
+                >(artifact_dir, config, device, checkpoint)
+                .build(ddpm.init::<B>(), optimizer.init(), scheduler)
+                .fit(train_loader, valid_loader);
+            save::<B, _>(trained, artifact_dir, "model", config);
+        }
+    }
+    Ok(())
+}
 
-    let learner = LearnerBuilder::new(artifact_dir)
+/// Learner settings, loggers and metrics shared by every architecture,
+/// resuming from `checkpoint` when there is one.
+#[cfg(not(target_family = "wasm"))]
+fn learner<B, T, V, M, O>(
+    artifact_dir: &str,
+    config: &TrainingConfig,
+    device: &B::Device,
+    checkpoint: Option<usize>,
+) -> LearnerBuilder<B, T, V, M, O, Schedule>
+where
+    B: AutodiffBackend,
+    T: Adaptor<LossInput<B>> + Send + Sync + 'static,
+    V: Adaptor<LossInput<B::InnerBackend>> + Send + Sync + 'static,
+    M: AutodiffModule<B> + Display + 'static,
+    O: burn::optim::Optimizer<M, B>,
+    M::Record: 'static,
+    O::Record: 'static,
+{
+    let (train_logger, valid_logger) =
+        loggers(artifact_dir, config.tensorboard, checkpoint);
+    let builder = LearnerBuilder::new(artifact_dir)
+        .metric_loggers(train_logger, valid_logger)
         .metric_train_numeric(LossMetric::new())
         .metric_valid_numeric(LossMetric::new())
-        .metric_train_numeric(ReconstructionLossMetric::new())
-        .metric_valid_numeric(ReconstructionLossMetric::new())
-        .metric_train_numeric(KLLossMetric::new())
-        .metric_valid_numeric(KLLossMetric::new())
         .metric_train_numeric(LearningRateMetric::new())
-        .metric_train_numeric(NvidiaUtilMetric::new())
-        .metric_valid_numeric(NvidiaUtilMetric::new())
-        .with_file_checkpointer(CompactRecorder::new())
-        .early_stopping(MetricEarlyStoppingStrategy::new::<LossMetric<B>>(
-            Aggregate::Mean,
-            Direction::Lowest,
-            Split::Valid,
-            StoppingCondition::NoImprovementSince {
-                n_epochs: config.early_stop_patience,
-            },
-        ))
+        .metric_train_numeric(CpuUsageMetric::new())
+        .metric_train_numeric(MemoryUsageMetric::new())
+        .metric_train_numeric(ThroughputMetric::new())
         .devices(vec![device.clone()])
         .num_epochs(config.num_epochs)
-        .build(
-            config.model.init::<B>(),
-            config.optimizer.init(),
-            scheduler,
+        .with_file_checkpointer(CompactRecorder::new())
+        .early_stopping(
+            MetricEarlyStoppingStrategy::new::<LossMetric<B>>(
+                Aggregate::Mean,
+                Direction::Lowest,
+                Split::Valid,
+                StoppingCondition::NoImprovementSince {
+                    n_epochs: config.early_stop_patience,
+                },
+            ),
         );
+    // GPU utilisation is only registered where a provider responds.
+    let builder = match GpuUsageMetric::detect() {
+        Some(gpu) => builder.metric_train_numeric(gpu),
+        None => builder,
+    };
+    // burn's TUI needs a terminal; sweep trials and detached runs log
+    // plain lines instead.
+    let builder = match std::io::stdout().is_terminal() {
+        true => builder,
+        false => builder.renderer(LogRenderer::new()),
+    };
+    match checkpoint {
+        Some(epoch) => builder.checkpoint(epoch),
+        None => builder,
+    }
+}
+/// The shared learner with the Gaussian VAE's metrics, for the model with
+/// or without EMA. `stopped` is set if the non-finite guard interrupts
+/// training.
+#[cfg(not(target_family = "wasm"))]
+fn gaussian_learner<B, M, O>(
+    artifact_dir: &str,
+    config: &TrainingConfig,
+    device: &B::Device,
+    checkpoint: Option<usize>,
+    stopped: &Arc<AtomicBool>,
+) -> LearnerBuilder<
+    B,
+    VAEOutput<B>,
+    VAEOutput<B::InnerBackend>,
+    M,
+    O,
+    Schedule,
+>
+where
+    B: AutodiffBackend,
+    M: AutodiffModule<B> + Display + 'static,
+    O: burn::optim::Optimizer<M, B>,
+    M::Record: 'static,
+    O::Record: 'static,
+{
+    let builder = learner(artifact_dir, config, device, checkpoint)
+        .metric_train_numeric(ReconstructionLossMetric::new())
+        .metric_valid_numeric(ReconstructionLossMetric::new())
+        .metric_train_numeric(KLLossMetric::new())
+        .metric_valid_numeric(KLLossMetric::new())
+        .metric_valid_numeric(ActiveUnitsMetric::new())
+        .metric_valid_numeric(MMDMetric::new())
+        .metric_valid_numeric(ChamferMetric::new())
+        .metric_valid_numeric(SlicedWassersteinMetric::new())
+        .metric_valid_numeric(
+            ConditionalFidelityMetric::new()
+                .with_source(config.data.clone()),
+        )
+        .metric_train_numeric(LabeledLossMetric::new())
+        .metric_valid_numeric(LabeledLossMetric::new())
+        .metric_train_numeric(UnlabeledLossMetric::new())
+        .metric_valid_numeric(UnlabeledLossMetric::new())
+        .metric_valid_numeric(LabelRMSEMetric::new());
+    let builder = match config.tensorboard && config.latent_histogram {
+        true => {
+            builder.metric_valid(LatentHistogramMetric::new(artifact_dir))
+        }
+        false => builder,
+    };
+    match config.model.non_finite {
+        NonFiniteGuard::Abort | NonFiniteGuard::Rollback(_) => {
+            let interrupter = builder.interrupter();
+            builder.metric_train(NonFiniteStopMetric::new(
+                interrupter,
+                stopped.clone(),
+            ))
+        }
+        NonFiniteGuard::Ignore | NonFiniteGuard::Skip => builder,
+    }
+}
 
-    let model = learner.fit(train_loader, valid_loader);
-
+#[cfg(not(target_family = "wasm"))]
+fn save<B: Backend, M: Module<B>>(
+    model: M,
+    artifact_dir: &str,
+    name: &str,
+    config: &TrainingConfig,
+) {
     model
         .clone()
         .save_file(
-            format!("{artifact_dir}/model"),
+            format!("{artifact_dir}/{name}"),
             &CompactRecorder::new(),
         )
         .expect("Trained model should be saved successfully");
 
-    use burn::record::{BinFileRecorder, FullPrecisionSettings};
-    let bin = BinFileRecorder::<FullPrecisionSettings>::new();
-    model
-        .save_file(format!("{artifact_dir}/model.bin"), &bin)
+    if config.quantize {
+        std::fs::write(
+            format!("{artifact_dir}/{name}.q8.bin"),
+            quantize::<B, _>(&model),
+        )
+        .expect("Quantized model should be saved successfully");
+    }
+    let precision = &config.export_precision;
+    if *precision != Precision::Full {
+        std::fs::write(
+            format!("{artifact_dir}/{name}.full.bin"),
+            Precision::Full.to_bytes(model.clone().into_record()),
+        )
         .expect("Model should be saved successfully");
+    }
+    std::fs::write(
+        format!("{artifact_dir}/{name}.bin"),
+        precision.to_bytes(model.into_record()),
+    )
+    .expect("Model should be saved successfully");
 }
@@ -0,0 +1,260 @@
+use burn::{
+    config::Config,
+    module::Module,
+    nn::{Embedding, EmbeddingConfig, Linear, LinearConfig},
+    tensor::{Distribution, Int, Tensor, activation, backend::Backend},
+};
+use dataset::LABEL_DIM;
+
+type Batches<B> = Tensor<B, 3>;
+type Classes<B> = Tensor<B, 2, Int>;
+type Modulation<B> = Vec<(Batches<B>, Batches<B>)>;
+
+#[derive(Config, Debug)]
+pub enum Conditioning {
+    Concat,
+    Embedding(usize),
+    Fourier(usize),
+    FiLM(usize),
+    Categorical(usize, usize),
+}
+
+impl Conditioning {
+    pub fn dim(&self) -> usize {
+        match self {
+            Self::Concat => LABEL_DIM,
+            Self::Embedding(dim) | Self::FiLM(dim) => *dim,
+            Self::Fourier(n_frequencies) => 2 * n_frequencies,
+            Self::Categorical(_, dim) => *dim,
+        }
+    }
+
+    pub fn is_categorical(&self) -> bool {
+        matches!(self, Self::Categorical(..))
+    }
+
+    fn embedding(&self) -> Option<LinearConfig> {
+        match self {
+            Self::Embedding(dim) | Self::FiLM(dim) => {
+                Some(LinearConfig::new(LABEL_DIM, *dim))
+            }
+            _ => None,
+        }
+    }
+
+    fn fourier(&self) -> Option<LinearConfig> {
+        match self {
+            Self::Fourier(n_frequencies) => {
+                Some(LinearConfig::new(LABEL_DIM, *n_frequencies))
+            }
+            _ => None,
+        }
+    }
+
+    fn n_classes(&self) -> usize {
+        match self {
+            Self::Categorical(n_classes, _) => *n_classes,
+            _ => 0,
+        }
+    }
+
+    fn classes(&self) -> Option<EmbeddingConfig> {
+        match self {
+            Self::Categorical(n_classes, dim) => {
+                Some(EmbeddingConfig::new(*n_classes, *dim))
+            }
+            _ => None,
+        }
+    }
+
+    fn film(&self, widths: &[usize], guided: bool) -> Vec<LinearConfig> {
+        match self {
+            Self::FiLM(dim) => widths
+                .iter()
+                .map(|width| {
+                    LinearConfig::new(dim + guided as usize, 2 * width)
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn init<B: Backend>(
+        &self,
+        widths: &[usize],
+        label_dropout: f64,
+    ) -> Conditioner<B> {
+        Conditioner {
+            embedding: self.embedding().map(|config| config.init()),
+            fourier: self.fourier().map(|config| config.init()),
+            classes: self.classes().map(|config| config.init()),
+            film: self
+                .film(widths, label_dropout > 0.)
+                .into_iter()
+                .map(|config| config.init())
+                .collect(),
+            label_dropout,
+            n_classes: self.n_classes(),
+        }
+    }
+
+    pub fn init_with<B: Backend>(
+        &self,
+        widths: &[usize],
+        label_dropout: f64,
+        record: ConditionerRecord<B>,
+    ) -> Conditioner<B> {
+        Conditioner {
+            embedding: self
+                .embedding()
+                .zip(record.embedding)
+                .map(|(config, record)| config.init_with(record)),
+            fourier: self
+                .fourier()
+                .zip(record.fourier)
+                .map(|(config, record)| config.init_with(record)),
+            classes: self
+                .classes()
+                .zip(record.classes)
+                .map(|(config, record)| config.init_with(record)),
+            film: self
+                .film(widths, label_dropout > 0.)
+                .into_iter()
+                .zip(record.film)
+                .map(|(config, record)| config.init_with(record))
+                .collect(),
+            label_dropout,
+            n_classes: self.n_classes(),
+        }
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct Conditioner<B: Backend> {
+    embedding: Option<Linear<B>>,
+    fourier: Option<Linear<B>>,
+    classes: Option<Embedding<B>>,
+    film: Vec<Linear<B>>,
+    label_dropout: f64,
+    n_classes: usize,
+}
+
+impl<B: Backend> Conditioner<B> {
+    /// Number of classes of a categorical model, 0 otherwise.
+    pub fn n_classes(&self) -> usize {
+        self.n_classes
+    }
+
+    /// Checks that a model is given the kind of label it was trained on,
+    /// and only classes it knows.
+    pub fn check_class(&self, class: Option<usize>) {
+        match (class, self.n_classes) {
+            (None, 0) => {}
+            (None, _) => panic!(
+                "Categorical models are conditioned on a class, not a continuous label"
+            ),
+            (Some(_), 0) => panic!(
+                "Only categorically conditioned models take a class"
+            ),
+            (Some(class), n_classes) => assert!(
+                class < n_classes,
+                "Class {class} is out of range for {n_classes} classes"
+            ),
+        }
+    }
+
+    /// Models trained with label dropout see an extra channel flagging
+    /// the null label, so it cannot be mistaken for a real label of 0.
+    fn guided(&self) -> bool {
+        self.label_dropout > 0.
+    }
+
+    /// Appends the null indicator to `c` for guided models.
+    fn with_indicator(
+        &self,
+        c: Batches<B>,
+        null: Batches<B>,
+    ) -> Batches<B> {
+        match self.guided() {
+            true => Tensor::cat(vec![c, null], 2),
+            false => c,
+        }
+    }
+
+    pub fn embed(&self, y: Batches<B>, classes: Classes<B>) -> Batches<B> {
+        let c = self.embed_label(y, classes);
+        let [batchsize, _, _] = c.dims();
+        let null = Tensor::zeros([batchsize, 1, 1]).to_device(&c.device());
+        self.with_indicator(c, null)
+    }
+
+    /// The unconditional counterpart of an embedding from [`Self::embed`].
+    pub fn null_like(&self, c: Batches<B>) -> Batches<B> {
+        let [batchsize, _, dim] = c.dims();
+        let device = c.device();
+        let label =
+            Tensor::zeros([batchsize, 1, dim - self.guided() as usize])
+                .to_device(&device);
+        let null = Tensor::ones([batchsize, 1, 1]).to_device(&device);
+        self.with_indicator(label, null)
+    }
+
+    fn embed_label(
+        &self,
+        y: Batches<B>,
+        classes: Classes<B>,
+    ) -> Batches<B> {
+        match (&self.embedding, &self.fourier, &self.classes) {
+            (Some(embedding), _, _) => {
+                activation::gelu(embedding.forward(y))
+            }
+            (_, Some(fourier), _) => {
+                let y = fourier.forward(y);
+                Tensor::cat(vec![y.clone().sin(), y.cos()], 2)
+            }
+            (_, _, Some(embedding)) => embedding.forward(classes),
+            _ => y,
+        }
+    }
+
+    pub fn forward(
+        &self,
+        y: Batches<B>,
+        classes: Classes<B>,
+    ) -> Batches<B> {
+        if !B::ad_enabled() || !self.guided() {
+            return self.embed(y, classes);
+        }
+
+        // Dropped labels are zeroed and flagged by the null indicator,
+        // which marks the unconditional label for classifier-free
+        // guidance.
+        let c = self.embed_label(y, classes);
+        let [batchsize, _, dim] = c.dims();
+        let keep = Tensor::random(
+            [batchsize, 1, 1],
+            Distribution::Bernoulli(1. - self.label_dropout),
+        )
+        .to_device(&c.device());
+
+        self.with_indicator(
+            c * keep.clone().repeat(2, dim),
+            keep.neg().add_scalar(1.),
+        )
+    }
+
+    pub fn modulation(&self, c: Batches<B>) -> Modulation<B> {
+        let batchsize = c.dims()[0];
+        self.film
+            .iter()
+            .map(|film| {
+                let params = film.forward(c.clone());
+                let width = params.dims()[2] / 2;
+                (
+                    params.clone().slice([0..batchsize, 0..1, 0..width]),
+                    params.slice([0..batchsize, 0..1, width..2 * width]),
+                )
+            })
+            .collect()
+    }
+}
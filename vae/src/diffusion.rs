@@ -0,0 +1,377 @@
+use crate::{
+    metric::DiffusionOutput,
+    mlp::{MLPBlock, MLPBlockConfig},
+    model::{to_batch, to_labels},
+};
+use burn::{
+    config::Config,
+    module::Module,
+    nn::{
+        Linear, LinearConfig,
+        loss::{MSELoss, Reduction},
+    },
+    tensor::{
+        Data, Distribution, Shape, Tensor,
+        backend::{AutodiffBackend, Backend},
+    },
+};
+use dataset::{INPUT_DIM, LABEL_DIM, Point, SpiralBatch};
+
+#[cfg(not(target_family = "wasm"))]
+use burn::train::{TrainOutput, TrainStep, ValidStep};
+
+type Batches<B> = Tensor<B, 3>;
+
+#[derive(Config, Debug)]
+pub enum Sampler {
+    Ancestral,
+    Ddim(usize),
+}
+
+#[derive(Module, Debug)]
+pub struct Diffusion<B: Backend> {
+    denoiser: MLPBlock<B, 3>,
+    label_embedding: Linear<B>,
+    fc: Linear<B>,
+    n_steps: usize,
+    beta_start: f64,
+    beta_end: f64,
+    time_dim: usize,
+    data_scale: f64,
+    pub ddim_steps: Option<usize>,
+}
+
+#[derive(Config, Debug)]
+pub struct DiffusionConfig {
+    pub denoiser: MLPBlockConfig,
+    #[config(default = 1000)]
+    n_steps: usize,
+    #[config(default = 1e-4)]
+    beta_start: f64,
+    #[config(default = 2e-2)]
+    beta_end: f64,
+    #[config(default = 32)]
+    time_dim: usize,
+    #[config(default = 16)]
+    label_dim: usize,
+    #[config(default = "Sampler::Ddim(50)")]
+    sampler: Sampler,
+    #[config(default = 10.)]
+    data_scale: f64,
+}
+
+impl DiffusionConfig {
+    fn ddim_steps(&self) -> Option<usize> {
+        match self.sampler {
+            Sampler::Ancestral => None,
+            Sampler::Ddim(steps) => Some(steps),
+        }
+    }
+
+    pub fn init<B: Backend>(&self) -> Diffusion<B> {
+        Diffusion {
+            denoiser: self.denoiser.init(),
+            label_embedding: LinearConfig::new(LABEL_DIM, self.label_dim)
+                .init(),
+            fc: LinearConfig::new(self.denoiser.output_dim, INPUT_DIM)
+                .init(),
+            n_steps: self.n_steps,
+            beta_start: self.beta_start,
+            beta_end: self.beta_end,
+            time_dim: self.time_dim,
+            data_scale: self.data_scale,
+            ddim_steps: self.ddim_steps(),
+        }
+    }
+
+    pub fn init_with<B: Backend>(
+        &self,
+        record: DiffusionRecord<B>,
+    ) -> Diffusion<B> {
+        Diffusion {
+            denoiser: self.denoiser.init_with(record.denoiser),
+            label_embedding: LinearConfig::new(LABEL_DIM, self.label_dim)
+                .init_with(record.label_embedding),
+            fc: LinearConfig::new(self.denoiser.output_dim, INPUT_DIM)
+                .init_with(record.fc),
+            n_steps: self.n_steps,
+            beta_start: self.beta_start,
+            beta_end: self.beta_end,
+            time_dim: self.time_dim,
+            data_scale: self.data_scale,
+            ddim_steps: self.ddim_steps(),
+        }
+    }
+}
+
+impl<B: Backend> Diffusion<B> {
+    fn betas(&self) -> Vec<f64> {
+        let step = (self.beta_end - self.beta_start)
+            / (self.n_steps.max(2) - 1) as f64;
+        (0..self.n_steps)
+            .map(|i| self.beta_start + step * i as f64)
+            .collect()
+    }
+
+    fn alpha_bars(&self) -> Vec<f64> {
+        self.betas()
+            .into_iter()
+            .scan(1., |alpha_bar, beta| {
+                *alpha_bar *= 1. - beta;
+                Some(*alpha_bar)
+            })
+            .collect()
+    }
+
+    fn time_embedding(&self, t: Tensor<B, 1>) -> Batches<B> {
+        let (n, half) = (t.dims()[0], self.time_dim / 2);
+        let freqs = (0..half)
+            .map(|i| (-(10_000f32.ln()) * i as f32 / half as f32).exp())
+            .collect();
+        let freqs = Tensor::<B, 1>::from_data(
+            Data::new(freqs, Shape::new([half])).convert(),
+        )
+        .to_device(&t.device())
+        .reshape([1, half])
+        .repeat(0, n);
+
+        let args = t.reshape([n, 1]).repeat(1, half) * freqs;
+        Tensor::cat(vec![args.clone().sin(), args.cos()], 1).reshape([
+            n,
+            1,
+            2 * half,
+        ])
+    }
+
+    fn denoise(
+        &self,
+        x: Batches<B>,
+        t: Tensor<B, 1>,
+        y: Tensor<B, 2>,
+    ) -> Batches<B> {
+        let n = x.dims()[0];
+        let t = self.time_embedding(t);
+        let y = self.label_embedding.forward(y.reshape([n, 1, LABEL_DIM]));
+
+        self.fc
+            .forward(self.denoiser.forward(Tensor::cat(vec![x, t, y], 2)))
+    }
+
+    fn steps(
+        &self,
+        n: usize,
+        step: usize,
+        device: &B::Device,
+    ) -> Tensor<B, 1> {
+        Tensor::ones([n]).to_device(device).mul_scalar(step as f64)
+    }
+
+    pub fn forward(
+        &self,
+        x: Batches<B>,
+        y: Tensor<B, 2>,
+    ) -> DiffusionOutput<B> {
+        let (n, device) = (x.dims()[0], x.device());
+        let x = x.div_scalar(self.data_scale);
+
+        let t = Tensor::<B, 1>::random(
+            [n],
+            Distribution::Uniform(0., self.n_steps as f64),
+        )
+        .to_device(&device)
+        .int();
+        let alpha_bars =
+            self.alpha_bars().into_iter().map(|a| a as f32).collect();
+        let alpha_bar = Tensor::<B, 1>::from_data(
+            Data::new(alpha_bars, Shape::new([self.n_steps])).convert(),
+        )
+        .to_device(&device)
+        .select(0, t.clone())
+        .reshape([n, 1, 1])
+        .repeat(2, INPUT_DIM);
+
+        let noise = Tensor::random_like(&x, Distribution::Normal(0., 1.));
+        let x_t = x * alpha_bar.clone().sqrt()
+            + noise.clone() * alpha_bar.neg().add_scalar(1.).sqrt();
+
+        let prediction = self.denoise(x_t, t.float(), y);
+        DiffusionOutput::new(MSELoss::new().forward(
+            prediction,
+            noise,
+            Reduction::Mean,
+        ))
+    }
+
+    fn ancestral(&self, x: Batches<B>, y: Tensor<B, 2>) -> Batches<B> {
+        let (n, device) = (x.dims()[0], x.device());
+        let (betas, alpha_bars) = (self.betas(), self.alpha_bars());
+
+        (0..self.n_steps).rev().fold(x, |x, step| {
+            let eps = self.denoise(
+                x.clone(),
+                self.steps(n, step, &device),
+                y.clone(),
+            );
+            let (beta, alpha_bar) = (betas[step], alpha_bars[step]);
+            let mean = (x - eps
+                .mul_scalar(beta / (1. - alpha_bar).sqrt()))
+            .div_scalar((1. - beta).sqrt());
+
+            match step {
+                0 => mean,
+                _ => {
+                    let z = Tensor::random_like(
+                        &mean,
+                        Distribution::Normal(0., 1.),
+                    );
+                    mean + z.mul_scalar(beta.sqrt())
+                }
+            }
+        })
+    }
+
+    fn ddim_schedule(&self, steps: usize) -> Vec<usize> {
+        let stride = (self.n_steps / steps.max(1)).max(1);
+        (0..self.n_steps).step_by(stride).collect()
+    }
+
+    fn ddim(
+        &self,
+        x: Batches<B>,
+        y: Tensor<B, 2>,
+        steps: usize,
+    ) -> Batches<B> {
+        let (n, device) = (x.dims()[0], x.device());
+        let alpha_bars = self.alpha_bars();
+        let schedule = self.ddim_schedule(steps);
+
+        schedule.iter().enumerate().rev().fold(x, |x, (i, &step)| {
+            let eps = self.denoise(
+                x.clone(),
+                self.steps(n, step, &device),
+                y.clone(),
+            );
+            let alpha_bar = alpha_bars[step];
+            let alpha_bar_prev = match i {
+                0 => 1.,
+                _ => alpha_bars[schedule[i - 1]],
+            };
+
+            let x_0 =
+                (x - eps.clone().mul_scalar((1. - alpha_bar).sqrt()))
+                    .div_scalar(alpha_bar.sqrt());
+            x_0.mul_scalar(alpha_bar_prev.sqrt())
+                + eps.mul_scalar((1. - alpha_bar_prev).sqrt())
+        })
+    }
+
+    fn ddim_inversion(
+        &self,
+        x: Batches<B>,
+        y: Tensor<B, 2>,
+        steps: usize,
+    ) -> Batches<B> {
+        let (n, device) = (x.dims()[0], x.device());
+        let alpha_bars = self.alpha_bars();
+        let schedule = self.ddim_schedule(steps);
+
+        schedule.iter().enumerate().fold(x, |x, (i, &step)| {
+            let alpha_bar_prev = match i {
+                0 => 1.,
+                _ => alpha_bars[schedule[i - 1]],
+            };
+            let eps = self.denoise(
+                x.clone(),
+                self.steps(n, step, &device),
+                y.clone(),
+            );
+            let alpha_bar = alpha_bars[step];
+
+            let x_0 =
+                (x - eps.clone().mul_scalar((1. - alpha_bar_prev).sqrt()))
+                    .div_scalar(alpha_bar_prev.sqrt());
+            x_0.mul_scalar(alpha_bar.sqrt())
+                + eps.mul_scalar((1. - alpha_bar).sqrt())
+        })
+    }
+
+    pub fn sample(
+        &self,
+        x: Batches<B>,
+        y: Tensor<B, 2>,
+        sampler: &Sampler,
+    ) -> Batches<B> {
+        match sampler {
+            Sampler::Ancestral => self.ancestral(x, y),
+            Sampler::Ddim(steps) => self.ddim(x, y, *steps),
+        }
+        .mul_scalar(self.data_scale)
+    }
+
+    fn sampler(&self) -> Sampler {
+        match self.ddim_steps {
+            Some(steps) => Sampler::Ddim(steps),
+            None => Sampler::Ancestral,
+        }
+    }
+
+    pub(crate) fn _generate(
+        &self,
+        t: f32,
+        n: usize,
+        device: &B::Device,
+    ) -> Batches<B> {
+        let x = Tensor::random(
+            [n, 1, INPUT_DIM],
+            Distribution::Normal(0., 1.),
+        )
+        .to_device(device);
+        let y = Tensor::from_floats([t])
+            .to_device(device)
+            .unsqueeze::<2>()
+            .repeat(0, n);
+
+        self.sample(x, y, &self.sampler())
+    }
+
+    pub(crate) fn _encode(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+    ) -> (Batches<B>, Batches<B>) {
+        let steps = self.ddim_steps.unwrap_or(self.n_steps);
+        let y = to_labels(labels);
+        let latent = self.ddim_inversion(
+            to_batch(x).div_scalar(self.data_scale),
+            y.clone(),
+            steps,
+        );
+        let reconstruction =
+            self.sample(latent.clone(), y, &Sampler::Ddim(steps));
+
+        (latent, reconstruction)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: AutodiffBackend> TrainStep<SpiralBatch<B>, DiffusionOutput<B>>
+    for Diffusion<B>
+{
+    fn step(
+        &self,
+        batch: SpiralBatch<B>,
+    ) -> TrainOutput<DiffusionOutput<B>> {
+        let prediction = self.forward(batch.points, batch.labels);
+
+        TrainOutput::new(self, prediction.loss.backward(), prediction)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> ValidStep<SpiralBatch<B>, DiffusionOutput<B>>
+    for Diffusion<B>
+{
+    fn step(&self, batch: SpiralBatch<B>) -> DiffusionOutput<B> {
+        self.forward(batch.points, batch.labels)
+    }
+}
@@ -0,0 +1,236 @@
+use crate::{
+    metric::FlowOutput,
+    mlp::{MLPBlock, MLPBlockConfig},
+    model::{to_batch, to_labels},
+};
+use burn::{
+    config::Config,
+    module::Module,
+    nn::{Linear, LinearConfig},
+    tensor::{
+        Distribution, Tensor,
+        backend::{AutodiffBackend, Backend},
+    },
+};
+use dataset::{INPUT_DIM, Point, SpiralBatch};
+
+#[cfg(not(target_family = "wasm"))]
+use burn::train::{TrainOutput, TrainStep, ValidStep};
+#[cfg(not(target_family = "wasm"))]
+use dataset::ToVec;
+
+type Batches<B> = Tensor<B, 3>;
+
+#[derive(Module, Debug)]
+pub struct Coupling<B: Backend> {
+    block: MLPBlock<B, 3>,
+    fc_scale: Linear<B>,
+    fc_shift: Linear<B>,
+    parity: usize,
+}
+
+impl<B: Backend> Coupling<B> {
+    fn masks(
+        &self,
+        n: usize,
+        device: &B::Device,
+    ) -> (Batches<B>, Batches<B>) {
+        let mask: [f32; INPUT_DIM] = std::array::from_fn(|d| {
+            (d + self.parity).is_multiple_of(2) as u8 as f32
+        });
+        let mask = Tensor::from_floats(mask)
+            .to_device(device)
+            .reshape([1, 1, INPUT_DIM])
+            .repeat(0, n);
+
+        (mask.clone(), mask.neg().add_scalar(1.))
+    }
+
+    fn params(
+        &self,
+        x: Batches<B>,
+        y: Batches<B>,
+    ) -> (Batches<B>, Batches<B>) {
+        let h = self.block.forward(Tensor::cat(vec![x, y], 2));
+        (
+            self.fc_scale.forward(h.clone()).tanh(),
+            self.fc_shift.forward(h),
+        )
+    }
+
+    pub fn forward(
+        &self,
+        x: Batches<B>,
+        y: Batches<B>,
+    ) -> (Batches<B>, Batches<B>) {
+        let (mask, inv) = self.masks(x.dims()[0], &x.device());
+        let x_masked = x.clone() * mask;
+        let (s, t) = self.params(x_masked.clone(), y);
+        let (s, t) = (s * inv.clone(), t * inv.clone());
+
+        let z = x_masked + inv * (x * s.clone().exp() + t);
+        (z, s.sum_dim(2))
+    }
+
+    pub fn inverse(&self, z: Batches<B>, y: Batches<B>) -> Batches<B> {
+        let (mask, inv) = self.masks(z.dims()[0], &z.device());
+        let z_masked = z.clone() * mask;
+        let (s, t) = self.params(z_masked.clone(), y);
+        let (s, t) = (s * inv.clone(), t * inv.clone());
+
+        z_masked + inv * ((z - t) * s.neg().exp())
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct Flow<B: Backend> {
+    layers: Vec<Coupling<B>>,
+    data_scale: f64,
+}
+
+#[derive(Config, Debug)]
+pub struct FlowConfig {
+    pub block_config: MLPBlockConfig,
+    #[config(default = 8)]
+    n_layers: usize,
+    #[config(default = 10.)]
+    data_scale: f64,
+}
+
+impl FlowConfig {
+    fn coupling<B: Backend>(&self, parity: usize) -> Coupling<B> {
+        let hidden = self.block_config.output_dim;
+        Coupling {
+            block: self.block_config.init(),
+            fc_scale: LinearConfig::new(hidden, INPUT_DIM).init(),
+            fc_shift: LinearConfig::new(hidden, INPUT_DIM).init(),
+            parity,
+        }
+    }
+
+    pub fn init<B: Backend>(&self) -> Flow<B> {
+        Flow {
+            layers: (0..self.n_layers)
+                .map(|i| self.coupling(i % 2))
+                .collect(),
+            data_scale: self.data_scale,
+        }
+    }
+
+    pub fn init_with<B: Backend>(&self, record: FlowRecord<B>) -> Flow<B> {
+        let hidden = self.block_config.output_dim;
+        Flow {
+            layers: record
+                .layers
+                .into_iter()
+                .enumerate()
+                .map(|(i, layer)| Coupling {
+                    block: self.block_config.init_with(layer.block),
+                    fc_scale: LinearConfig::new(hidden, INPUT_DIM)
+                        .init_with(layer.fc_scale),
+                    fc_shift: LinearConfig::new(hidden, INPUT_DIM)
+                        .init_with(layer.fc_shift),
+                    parity: i % 2,
+                })
+                .collect(),
+            data_scale: self.data_scale,
+        }
+    }
+}
+
+impl<B: Backend> Flow<B> {
+    fn _forward(
+        &self,
+        x: Batches<B>,
+        y: Tensor<B, 2>,
+    ) -> (Batches<B>, Batches<B>) {
+        let batchsize = x.dims()[0];
+        let y = y.reshape([batchsize as i32, 1, -1]);
+        let log_det = Tensor::zeros([batchsize, 1, 1])
+            .to_device(&x.device())
+            .sub_scalar(INPUT_DIM as f64 * self.data_scale.ln());
+
+        self.layers.iter().fold(
+            (x.div_scalar(self.data_scale), log_det),
+            |(x, log_det), layer| {
+                let (z, layer_log_det) = layer.forward(x, y.clone());
+                (z, log_det + layer_log_det)
+            },
+        )
+    }
+
+    fn _log_prob(&self, x: Batches<B>, y: Tensor<B, 2>) -> Batches<B> {
+        let (z, log_det) = self._forward(x, y);
+        let log_norm =
+            0.5 * INPUT_DIM as f64 * (2. * std::f64::consts::PI).ln();
+
+        log_det
+            - z.powf(2.).sum_dim(2).mul_scalar(0.5).add_scalar(log_norm)
+    }
+
+    pub fn forward(
+        &self,
+        x: Batches<B>,
+        y: Tensor<B, 2>,
+    ) -> FlowOutput<B> {
+        FlowOutput::new(self._log_prob(x, y).neg().mean())
+    }
+
+    pub(crate) fn _generate(
+        &self,
+        t: f32,
+        n: usize,
+        device: &B::Device,
+    ) -> Batches<B> {
+        let z = Tensor::random(
+            [n, 1, INPUT_DIM],
+            Distribution::Normal(0., 1.),
+        )
+        .to_device(device);
+        let t = Tensor::from_floats([t])
+            .to_device(device)
+            .unsqueeze::<3>()
+            .repeat(0, n);
+
+        self.layers
+            .iter()
+            .rev()
+            .fold(z, |z, layer| layer.inverse(z, t.clone()))
+            .mul_scalar(self.data_scale)
+    }
+
+    pub(crate) fn _encode(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+    ) -> (Batches<B>, Batches<B>) {
+        self._forward(to_batch(x), to_labels(labels))
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn log_prob(&self, x: Vec<Point>, labels: Vec<f32>) -> Vec<f32> {
+        self._log_prob(to_batch(x), to_labels(labels))
+            .to_vec()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: AutodiffBackend> TrainStep<SpiralBatch<B>, FlowOutput<B>>
+    for Flow<B>
+{
+    fn step(&self, batch: SpiralBatch<B>) -> TrainOutput<FlowOutput<B>> {
+        let prediction = self.forward(batch.points, batch.labels);
+
+        TrainOutput::new(self, prediction.nll.backward(), prediction)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> ValidStep<SpiralBatch<B>, FlowOutput<B>> for Flow<B> {
+    fn step(&self, batch: SpiralBatch<B>) -> FlowOutput<B> {
+        self.forward(batch.points, batch.labels)
+    }
+}
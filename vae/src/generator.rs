@@ -0,0 +1,435 @@
+use crate::{diffusion::Diffusion, flow::Flow, model::VAE, vq::VQVAE};
+use burn::{
+    module::Module,
+    tensor::{Tensor, backend::Backend},
+};
+use dataset::{Point, ToPoints, ToVec};
+
+type Batches<B> = Tensor<B, 3>;
+
+#[derive(Debug)]
+pub enum Generator<B: Backend> {
+    Gaussian(Box<VAE<B>>),
+    VectorQuantized(Box<VQVAE<B>>),
+    Flow(Box<Flow<B>>),
+    Diffusion(Box<Diffusion<B>>),
+}
+
+/// An operation on whichever model a [`Generator`] wraps, for callers
+/// that only need its [`Module`] interface.
+pub trait ModelFn<B: Backend> {
+    type Output;
+
+    fn call<M: Module<B>>(self, model: &M) -> Self::Output;
+}
+
+impl<B: Backend> Generator<B> {
+    /// Applies `f` to the wrapped model.
+    pub fn with_model<F: ModelFn<B>>(&self, f: F) -> F::Output {
+        match self {
+            Self::Gaussian(model) => f.call(model.as_ref()),
+            Self::VectorQuantized(model) => f.call(model.as_ref()),
+            Self::Flow(model) => f.call(model.as_ref()),
+            Self::Diffusion(model) => f.call(model.as_ref()),
+        }
+    }
+
+    fn _generate(
+        &self,
+        t: f32,
+        n: usize,
+        guidance_scale: Option<f64>,
+        device: &B::Device,
+    ) -> Batches<B> {
+        match self {
+            Self::Gaussian(model) => model._generate(
+                t,
+                n,
+                guidance_scale.unwrap_or(model.guidance_scale),
+                device,
+            ),
+            _ if guidance_scale.is_some() => {
+                panic!("Only Gaussian VAEs support guidance")
+            }
+            Self::VectorQuantized(model) => model._generate(t, n, device),
+            Self::Flow(model) => model._generate(t, n, device),
+            Self::Diffusion(model) => model._generate(t, n, device),
+        }
+    }
+
+    fn _generate_class(
+        &self,
+        class: usize,
+        n: usize,
+        guidance_scale: Option<f64>,
+        device: &B::Device,
+    ) -> Batches<B> {
+        match self {
+            Self::Gaussian(model) => model._generate_class(
+                class,
+                n,
+                guidance_scale.unwrap_or(model.guidance_scale),
+                device,
+            ),
+            _ => panic!("Only Gaussian VAEs support class conditioning"),
+        }
+    }
+
+    fn _encode(&self, x: Vec<Point>) -> (Batches<B>, Batches<B>) {
+        match self {
+            Self::Gaussian(model) => model._encode(x),
+            Self::VectorQuantized(_) => panic!(
+                "VQ-VAEs have no Gaussian posterior, use encode_quantized"
+            ),
+            _ => panic!(
+                "Flows and diffusion models are conditional, use encode_conditional"
+            ),
+        }
+    }
+
+    fn _encode_conditional(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+    ) -> (Batches<B>, Batches<B>) {
+        match self {
+            Self::Flow(model) => model._encode(x, labels),
+            Self::Diffusion(model) => model._encode(x, labels),
+            _ => panic!(
+                "Only flows and diffusion models encode conditionally"
+            ),
+        }
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn generate(
+        &self,
+        t: f32,
+        n: usize,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate(t, n, None, device).to_points()
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn generate(
+        &self,
+        t: f32,
+        n: usize,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate(t, n, None, device).to_points().await
+    }
+
+    /// Samples at a guidance scale other than the configured one.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn generate_guided(
+        &self,
+        t: f32,
+        n: usize,
+        guidance_scale: f64,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate(t, n, Some(guidance_scale), device)
+            .to_points()
+    }
+
+    /// Samples at a guidance scale other than the configured one.
+    #[cfg(target_family = "wasm")]
+    pub async fn generate_guided(
+        &self,
+        t: f32,
+        n: usize,
+        guidance_scale: f64,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate(t, n, Some(guidance_scale), device)
+            .to_points()
+            .await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn generate_class(
+        &self,
+        class: usize,
+        n: usize,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate_class(class, n, None, device).to_points()
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn generate_class(
+        &self,
+        class: usize,
+        n: usize,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate_class(class, n, None, device)
+            .to_points()
+            .await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn generate_class_guided(
+        &self,
+        class: usize,
+        n: usize,
+        guidance_scale: f64,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate_class(class, n, Some(guidance_scale), device)
+            .to_points()
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn generate_class_guided(
+        &self,
+        class: usize,
+        n: usize,
+        guidance_scale: f64,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate_class(class, n, Some(guidance_scale), device)
+            .to_points()
+            .await
+    }
+
+    /// Posterior mean and log-variance of a Gaussian VAE.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn encode(&self, x: Vec<Point>) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let (a, b) = self._encode(x);
+        (a.to_vec(), b.to_vec())
+    }
+
+    /// Posterior mean and log-variance of a Gaussian VAE.
+    #[cfg(target_family = "wasm")]
+    pub async fn encode(
+        &self,
+        x: Vec<Point>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let (a, b) = self._encode(x);
+        (a.to_vec().await, b.to_vec().await)
+    }
+
+    /// Latents of a flow with their log-determinant, or DDIM latents of
+    /// a diffusion model with their reconstruction.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn encode_conditional(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let (a, b) = self._encode_conditional(x, labels);
+        (a.to_vec(), b.to_vec())
+    }
+
+    /// Latents of a flow with their log-determinant, or DDIM latents of
+    /// a diffusion model with their reconstruction.
+    #[cfg(target_family = "wasm")]
+    pub async fn encode_conditional(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let (a, b) = self._encode_conditional(x, labels);
+        (a.to_vec().await, b.to_vec().await)
+    }
+
+    /// Continuous encoder output and its nearest codebook entries.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn encode_quantized(
+        &self,
+        x: Vec<Point>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let (z, quantized) = self.vq()._encode(x);
+        (z.to_vec(), quantized.to_vec())
+    }
+
+    /// Continuous encoder output and its nearest codebook entries.
+    #[cfg(target_family = "wasm")]
+    pub async fn encode_quantized(
+        &self,
+        x: Vec<Point>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let (z, quantized) = self.vq()._encode(x);
+        (z.to_vec().await, quantized.to_vec().await)
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn predict_label(&self, x: Vec<Point>) -> Vec<f32> {
+        self.vae()
+            .expect("Only Gaussian VAEs support label prediction")
+            .predict_label(x)
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn predict_label(&self, x: Vec<Point>) -> Vec<f32> {
+        self.vae()
+            .expect("Only Gaussian VAEs support label prediction")
+            .predict_label(x)
+            .await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn encode_std(
+        &self,
+        x: Vec<Point>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        self.vae()
+            .expect("Only Gaussian VAEs have a posterior scale")
+            .encode_std(x)
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn encode_std(
+        &self,
+        x: Vec<Point>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        self.vae()
+            .expect("Only Gaussian VAEs have a posterior scale")
+            .encode_std(x)
+            .await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn encode_latent(
+        &self,
+        x: Vec<Point>,
+        deterministic: bool,
+    ) -> Vec<Vec<f32>> {
+        self.vae()
+            .expect("Only Gaussian VAEs have a posterior to sample")
+            .encode_latent(x, deterministic)
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn encode_latent(
+        &self,
+        x: Vec<Point>,
+        deterministic: bool,
+    ) -> Vec<Vec<f32>> {
+        self.vae()
+            .expect("Only Gaussian VAEs have a posterior to sample")
+            .encode_latent(x, deterministic)
+            .await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn sample_posterior(
+        &self,
+        x: Vec<Point>,
+        n_samples: usize,
+    ) -> Vec<Vec<Vec<f32>>> {
+        self.vae()
+            .expect("Only Gaussian VAEs have a posterior to sample")
+            .sample_posterior(x, n_samples)
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn sample_posterior(
+        &self,
+        x: Vec<Point>,
+        n_samples: usize,
+    ) -> Vec<Vec<Vec<f32>>> {
+        self.vae()
+            .expect("Only Gaussian VAEs have a posterior to sample")
+            .sample_posterior(x, n_samples)
+            .await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn score(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+    ) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        self.vae()
+            .expect("Only Gaussian VAEs support ELBO scoring")
+            .score(x, labels, classes)
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn score(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+    ) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        self.vae()
+            .expect("Only Gaussian VAEs support ELBO scoring")
+            .score(x, labels, classes)
+            .await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn calibrate_threshold(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+        percentile: f64,
+    ) -> f32 {
+        self.vae()
+            .expect("Only Gaussian VAEs support ELBO scoring")
+            .calibrate_threshold(x, labels, classes, percentile)
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn calibrate_threshold(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+        percentile: f64,
+    ) -> f32 {
+        self.vae()
+            .expect("Only Gaussian VAEs support ELBO scoring")
+            .calibrate_threshold(x, labels, classes, percentile)
+            .await
+    }
+
+    /// Number of classes of a categorical model, 0 otherwise.
+    pub fn n_classes(&self) -> usize {
+        self.vae().map_or(0, VAE::n_classes)
+    }
+
+    pub fn vae(&self) -> Option<&VAE<B>> {
+        match self {
+            Self::Gaussian(model) => Some(model),
+            _ => None,
+        }
+    }
+
+    fn vq(&self) -> &VQVAE<B> {
+        match self {
+            Self::VectorQuantized(model) => model,
+            _ => panic!("Only VQ-VAEs have a quantized encoding"),
+        }
+    }
+}
+
+impl<B: Backend> From<VAE<B>> for Generator<B> {
+    fn from(model: VAE<B>) -> Self {
+        Self::Gaussian(Box::new(model))
+    }
+}
+
+impl<B: Backend> From<VQVAE<B>> for Generator<B> {
+    fn from(model: VQVAE<B>) -> Self {
+        Self::VectorQuantized(Box::new(model))
+    }
+}
+
+impl<B: Backend> From<Flow<B>> for Generator<B> {
+    fn from(model: Flow<B>) -> Self {
+        Self::Flow(Box::new(model))
+    }
+}
+
+impl<B: Backend> From<Diffusion<B>> for Generator<B> {
+    fn from(model: Diffusion<B>) -> Self {
+        Self::Diffusion(Box::new(model))
+    }
+}
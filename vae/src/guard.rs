@@ -0,0 +1,76 @@
+use burn::{
+    config::Config,
+    constant,
+    tensor::{ElementConversion, Tensor, backend::Backend},
+};
+use std::fmt;
+
+/// What a training step does when its loss is NaN or infinite. Checking
+/// reads the loss back from the device, which synchronises every step.
+#[derive(Config, Debug)]
+pub enum NonFiniteGuard {
+    /// Apply the step anyway, without checking the loss.
+    Ignore,
+    /// Drop the step's gradients.
+    Skip,
+    /// Drop the step's gradients and stop training.
+    Abort,
+    /// Restart from the last epoch checkpoint, at most this many times.
+    Rollback(usize),
+}
+
+constant!(NonFiniteGuard);
+
+/// What a training step does with its gradients after
+/// [`NonFiniteGuard::check`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Apply,
+    Skip,
+    /// Skip the step and stop training.
+    Stop,
+}
+
+/// Returned by training when [`NonFiniteGuard::Abort`] or
+/// [`NonFiniteGuard::Rollback`] stopped it.
+#[derive(Debug)]
+pub struct NonFiniteLoss;
+
+impl fmt::Display for NonFiniteLoss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Training stopped on a non-finite loss")
+    }
+}
+
+impl std::error::Error for NonFiniteLoss {}
+
+impl NonFiniteGuard {
+    /// Whether the step may apply its gradients. Non-finite losses are
+    /// logged together with the batch that produced them.
+    pub fn check<B: Backend>(
+        &self,
+        loss: &Tensor<B, 1>,
+        points: &Tensor<B, 3>,
+    ) -> Verdict {
+        if let Self::Ignore = self {
+            return Verdict::Apply;
+        }
+        let loss = loss.clone().into_scalar().elem::<f64>();
+        if loss.is_finite() {
+            return Verdict::Apply;
+        }
+
+        let batch = points
+            .clone()
+            .into_data()
+            .value
+            .into_iter()
+            .map(|x| x.elem::<f32>())
+            .collect::<Vec<_>>();
+        log::warn!("Non-finite loss {loss} on batch {batch:?}");
+        match self {
+            Self::Ignore | Self::Skip => Verdict::Skip,
+            Self::Abort | Self::Rollback(_) => Verdict::Stop,
+        }
+    }
+}
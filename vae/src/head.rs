@@ -0,0 +1,60 @@
+use crate::mlp::{MLPBlock, MLPBlockConfig};
+use burn::{
+    config::Config,
+    module::Module,
+    nn::{Linear, LinearConfig},
+    tensor::{Tensor, backend::Backend},
+};
+use dataset::LABEL_DIM;
+
+type Batches<B> = Tensor<B, 3>;
+
+/// Gaussian q(y|x) used to infer missing conditioning labels.
+#[derive(Module, Debug)]
+pub struct LabelHead<B: Backend> {
+    block: MLPBlock<B, 3>,
+    fc_mu: Linear<B>,
+    fc_logvar: Linear<B>,
+    pub weight: f64,
+}
+
+#[derive(Config, Debug)]
+pub struct LabelHeadConfig {
+    pub block_config: MLPBlockConfig,
+    #[config(default = 1e0)]
+    weight: f64,
+}
+
+impl LabelHeadConfig {
+    fn fc(&self) -> LinearConfig {
+        LinearConfig::new(self.block_config.output_dim, LABEL_DIM)
+    }
+
+    pub fn init<B: Backend>(&self) -> LabelHead<B> {
+        LabelHead {
+            block: self.block_config.init(),
+            fc_mu: self.fc().init(),
+            fc_logvar: self.fc().init(),
+            weight: self.weight,
+        }
+    }
+
+    pub fn init_with<B: Backend>(
+        &self,
+        record: LabelHeadRecord<B>,
+    ) -> LabelHead<B> {
+        LabelHead {
+            block: self.block_config.init_with(record.block),
+            fc_mu: self.fc().init_with(record.fc_mu),
+            fc_logvar: self.fc().init_with(record.fc_logvar),
+            weight: self.weight,
+        }
+    }
+}
+
+impl<B: Backend> LabelHead<B> {
+    pub fn forward(&self, x: Batches<B>) -> (Batches<B>, Batches<B>) {
+        let h = self.block.forward(x);
+        (self.fc_mu.forward(h.clone()), self.fc_logvar.forward(h))
+    }
+}
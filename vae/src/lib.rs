@@ -1,10 +1,33 @@
+pub mod conditioning;
 pub mod loss;
 pub mod metric;
+pub mod quality;
 
 mod mlp;
-pub use mlp::MLPBlockConfig;
+pub use mlp::{Activation, MLPBlockConfig, Norm, Residual};
+
+mod head;
+pub use head::LabelHeadConfig;
+
+mod posterior;
+pub use posterior::Posterior;
+
+mod guard;
+pub use guard::{NonFiniteGuard, NonFiniteLoss, Verdict};
 
 mod model;
 pub use model::{
     DecoderConfig, EncoderConfig, VAE as Model, VAEConfig as ModelConfig,
 };
+
+mod vq;
+pub use vq::{CodebookConfig, VQVAE, VQVAEConfig};
+
+mod flow;
+pub use flow::{Flow, FlowConfig};
+
+mod diffusion;
+pub use diffusion::{Diffusion, DiffusionConfig, Sampler};
+
+mod generator;
+pub use generator::{Generator, ModelFn};
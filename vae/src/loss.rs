@@ -17,6 +17,26 @@ impl KLLoss {
             .mean()
             .mul_scalar(-0.5)
     }
+
+    pub fn per_dim<B: Backend>(
+        &self,
+        mean: Tensor<B, 3>,
+        log_var: Tensor<B, 3>,
+    ) -> Tensor<B, 2> {
+        let [batchsize, _, latent_dim] = mean.dims();
+        (log_var.clone().add_scalar(1.) - mean.powf(2.) - log_var.exp())
+            .reshape([batchsize, latent_dim])
+            .mul_scalar(-0.5)
+    }
+
+    pub fn per_point<B: Backend>(
+        &self,
+        mean: Tensor<B, 3>,
+        log_var: Tensor<B, 3>,
+    ) -> Tensor<B, 1> {
+        let batchsize = mean.dims()[0];
+        self.per_dim(mean, log_var).mean_dim(1).reshape([batchsize])
+    }
 }
 
 impl Default for KLLoss {
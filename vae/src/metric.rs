@@ -1,28 +1,152 @@
-use burn::tensor::{ElementConversion, Tensor, backend::Backend};
+use crate::quality::{Chamfer, MMD, SampleDistance, SlicedWasserstein};
 #[cfg(not(target_family = "wasm"))]
-use burn::train::metric::{
-    Adaptor, LossInput, Metric, MetricEntry, MetricMetadata, Numeric,
-    state::{FormatOptions, NumericMetricState},
+use burn::train::{
+    TrainingInterrupter,
+    metric::{
+        Adaptor, LossInput, Metric, MetricEntry, MetricMetadata, Numeric,
+        state::{FormatOptions, NumericMetricState},
+    },
 };
+use burn::{
+    constant,
+    tensor::{ElementConversion, Int, Tensor, backend::Backend},
+};
+use dataset::{DataSource, Point};
+#[cfg(not(target_family = "wasm"))]
+use dataset::{ToPoints, ToVec};
+#[cfg(not(target_family = "wasm"))]
+use std::sync::atomic::AtomicBool;
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+/// Unreduced losses for each sample in the batch, with the posterior
+/// means they came from. `kl` is the unweighted KL averaged over latent
+/// dims, as in training.
+pub struct SampleLosses<B: Backend> {
+    pub recon: Tensor<B, 1>,
+    pub kl: Tensor<B, 1>,
+    pub kl_per_dim: Tensor<B, 2>,
+    pub latent_mean: Tensor<B, 2>,
+}
+
+impl<B: Backend> SampleLosses<B> {
+    pub fn new(
+        recon: Tensor<B, 1>,
+        kl: Tensor<B, 1>,
+        kl_per_dim: Tensor<B, 2>,
+        latent_mean: Tensor<B, 2>,
+    ) -> Self {
+        Self {
+            recon,
+            kl,
+            kl_per_dim,
+            latent_mean,
+        }
+    }
+}
 
 pub struct VAEOutput<B: Backend> {
     pub recon_loss: Tensor<B, 1>,
     pub kl_loss: Tensor<B, 1>,
+    pub label_loss: Tensor<B, 1>,
+    pub labeled_loss: Tensor<B, 1>,
+    pub unlabeled_loss: Tensor<B, 1>,
+    pub label_error: Tensor<B, 1>,
+    pub samples: SampleLosses<B>,
+    pub quality: Option<SampleQualityInput<B>>,
+    /// Set when a non-finite loss asks training to stop.
+    pub stop: bool,
 }
 
 impl<B: Backend> VAEOutput<B> {
-    pub fn new(recon_loss: Tensor<B, 1>, kl_loss: Tensor<B, 1>) -> Self {
+    pub fn new(
+        recon_loss: Tensor<B, 1>,
+        kl_loss: Tensor<B, 1>,
+        label_loss: Tensor<B, 1>,
+        labeled_loss: Tensor<B, 1>,
+        unlabeled_loss: Tensor<B, 1>,
+        label_error: Tensor<B, 1>,
+        samples: SampleLosses<B>,
+    ) -> Self {
         Self {
             recon_loss,
             kl_loss,
+            label_loss,
+            labeled_loss,
+            unlabeled_loss,
+            label_error,
+            samples,
+            quality: None,
+            stop: false,
+        }
+    }
+
+    pub fn with_quality(mut self, quality: SampleQualityInput<B>) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    pub fn with_stop(mut self, stop: bool) -> Self {
+        self.stop = stop;
+        self
+    }
+
+    pub fn loss(&self) -> Tensor<B, 1> {
+        self.recon_loss.clone()
+            + self.kl_loss.clone()
+            + self.label_loss.clone()
+    }
+}
+
+pub struct VQVAEOutput<B: Backend> {
+    pub recon_loss: Tensor<B, 1>,
+    pub commitment_loss: Tensor<B, 1>,
+    pub perplexity: Tensor<B, 1>,
+    pub usage: Tensor<B, 1>,
+}
+
+impl<B: Backend> VQVAEOutput<B> {
+    pub fn new(
+        recon_loss: Tensor<B, 1>,
+        commitment_loss: Tensor<B, 1>,
+        perplexity: Tensor<B, 1>,
+        usage: Tensor<B, 1>,
+    ) -> Self {
+        Self {
+            recon_loss,
+            commitment_loss,
+            perplexity,
+            usage,
         }
     }
 }
 
+pub struct FlowOutput<B: Backend> {
+    pub nll: Tensor<B, 1>,
+}
+
+impl<B: Backend> FlowOutput<B> {
+    pub fn new(nll: Tensor<B, 1>) -> Self {
+        Self { nll }
+    }
+}
+
+pub struct DiffusionOutput<B: Backend> {
+    pub loss: Tensor<B, 1>,
+}
+
+impl<B: Backend> DiffusionOutput<B> {
+    pub fn new(loss: Tensor<B, 1>) -> Self {
+        Self { loss }
+    }
+}
+
 #[cfg(not(target_family = "wasm"))]
 impl<B: Backend> Adaptor<LossInput<B>> for VAEOutput<B> {
     fn adapt(&self) -> LossInput<B> {
-        LossInput::new(self.kl_loss.clone() + self.recon_loss.clone())
+        LossInput::new(self.loss())
     }
 }
 
@@ -149,3 +273,947 @@ impl<B: Backend> Numeric for KLLossMetric<B> {
         self.state.value()
     }
 }
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<LabeledLossInput<B>> for VAEOutput<B> {
+    fn adapt(&self) -> LabeledLossInput<B> {
+        LabeledLossInput::new(self.labeled_loss.clone())
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<UnlabeledLossInput<B>> for VAEOutput<B> {
+    fn adapt(&self) -> UnlabeledLossInput<B> {
+        UnlabeledLossInput::new(self.unlabeled_loss.clone())
+    }
+}
+
+pub struct LabeledLossInput<B: Backend> {
+    tensor: Tensor<B, 1>,
+}
+
+impl<B: Backend> LabeledLossInput<B> {
+    pub fn new(tensor: Tensor<B, 1>) -> Self {
+        Self { tensor }
+    }
+}
+
+#[derive(Default)]
+pub struct LabeledLossMetric<B: Backend> {
+    #[cfg(not(target_family = "wasm"))]
+    state: NumericMetricState,
+    _b: B,
+}
+
+impl<B: Backend> LabeledLossMetric<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Metric for LabeledLossMetric<B> {
+    type Input = LabeledLossInput<B>;
+    const NAME: &'static str = "Labeled Loss";
+
+    fn update(
+        &mut self,
+        loss: &Self::Input,
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let loss = f64::from_elem(
+            loss.tensor.clone().mean().into_data().value[0],
+        );
+        self.state.update(
+            loss,
+            1,
+            FormatOptions::new(Self::NAME).precision(2),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.state.reset();
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Numeric for LabeledLossMetric<B> {
+    fn value(&self) -> f64 {
+        self.state.value()
+    }
+}
+
+pub struct UnlabeledLossInput<B: Backend> {
+    tensor: Tensor<B, 1>,
+}
+
+impl<B: Backend> UnlabeledLossInput<B> {
+    pub fn new(tensor: Tensor<B, 1>) -> Self {
+        Self { tensor }
+    }
+}
+
+#[derive(Default)]
+pub struct UnlabeledLossMetric<B: Backend> {
+    #[cfg(not(target_family = "wasm"))]
+    state: NumericMetricState,
+    _b: B,
+}
+
+impl<B: Backend> UnlabeledLossMetric<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Metric for UnlabeledLossMetric<B> {
+    type Input = UnlabeledLossInput<B>;
+    const NAME: &'static str = "Unlabeled Loss";
+
+    fn update(
+        &mut self,
+        loss: &Self::Input,
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let loss = f64::from_elem(
+            loss.tensor.clone().mean().into_data().value[0],
+        );
+        self.state.update(
+            loss,
+            1,
+            FormatOptions::new(Self::NAME).precision(2),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.state.reset();
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Numeric for UnlabeledLossMetric<B> {
+    fn value(&self) -> f64 {
+        self.state.value()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<LabelRMSEInput<B>> for VAEOutput<B> {
+    fn adapt(&self) -> LabelRMSEInput<B> {
+        LabelRMSEInput::new(self.label_error.clone())
+    }
+}
+
+pub struct LabelRMSEInput<B: Backend> {
+    tensor: Tensor<B, 1>,
+}
+
+impl<B: Backend> LabelRMSEInput<B> {
+    pub fn new(tensor: Tensor<B, 1>) -> Self {
+        Self { tensor }
+    }
+}
+
+#[derive(Default)]
+pub struct LabelRMSEMetric<B: Backend> {
+    #[cfg(not(target_family = "wasm"))]
+    state: NumericMetricState,
+    _b: B,
+}
+
+impl<B: Backend> LabelRMSEMetric<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Metric for LabelRMSEMetric<B> {
+    type Input = LabelRMSEInput<B>;
+    const NAME: &'static str = "Label RMSE";
+
+    fn update(
+        &mut self,
+        error: &Self::Input,
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let mse = f64::from_elem(
+            error.tensor.clone().mean().into_data().value[0],
+        );
+        self.state.update(
+            mse.sqrt(),
+            1,
+            FormatOptions::new(Self::NAME).precision(2),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.state.reset();
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Numeric for LabelRMSEMetric<B> {
+    fn value(&self) -> f64 {
+        self.state.value()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<ActiveUnitsInput<B>> for VAEOutput<B> {
+    fn adapt(&self) -> ActiveUnitsInput<B> {
+        ActiveUnitsInput::new(
+            self.samples.latent_mean.clone(),
+            self.samples.kl_per_dim.clone(),
+        )
+    }
+}
+
+pub struct ActiveUnitsInput<B: Backend> {
+    latent_mean: Tensor<B, 2>,
+    kl_per_dim: Tensor<B, 2>,
+}
+
+impl<B: Backend> ActiveUnitsInput<B> {
+    pub fn new(
+        latent_mean: Tensor<B, 2>,
+        kl_per_dim: Tensor<B, 2>,
+    ) -> Self {
+        Self {
+            latent_mean,
+            kl_per_dim,
+        }
+    }
+
+    pub fn latent_mean(&self) -> &Tensor<B, 2> {
+        &self.latent_mean
+    }
+}
+
+#[derive(Default)]
+struct LatentStats {
+    n: usize,
+    sum: Vec<f64>,
+    sum_sq: Vec<f64>,
+    kl: Vec<f64>,
+}
+
+impl LatentStats {
+    fn update(&mut self, mu: Vec<f64>, kl: Vec<f64>, dim: usize) {
+        if self.sum.len() != dim {
+            *self = Self {
+                n: 0,
+                sum: vec![0.; dim],
+                sum_sq: vec![0.; dim],
+                kl: vec![0.; dim],
+            };
+        }
+        mu.chunks(dim).zip(kl.chunks(dim)).for_each(|(mu, kl)| {
+            self.n += 1;
+            (0..dim).for_each(|d| {
+                self.sum[d] += mu[d];
+                self.sum_sq[d] += mu[d] * mu[d];
+                self.kl[d] += kl[d];
+            });
+        });
+    }
+
+    fn variances(&self) -> Vec<f64> {
+        let n = self.n.max(1) as f64;
+        self.sum
+            .iter()
+            .zip(&self.sum_sq)
+            .map(|(sum, sum_sq)| sum_sq / n - (sum / n).powi(2))
+            .collect()
+    }
+
+    fn kl(&self) -> Vec<f64> {
+        let n = self.n.max(1) as f64;
+        self.kl.iter().map(|kl| kl / n).collect()
+    }
+}
+
+/// Counts latent dims whose posterior mean varies across the data by more
+/// than `threshold` (Burda et al., 2016), and reports the KL of each dim.
+/// Statistics accumulate over the epoch, so the last value is exact.
+pub struct ActiveUnitsMetric<B: Backend> {
+    threshold: f64,
+    stats: LatentStats,
+    _b: B,
+}
+
+impl<B: Backend> Default for ActiveUnitsMetric<B> {
+    fn default() -> Self {
+        Self {
+            threshold: 1e-2,
+            stats: LatentStats::default(),
+            _b: B::default(),
+        }
+    }
+}
+
+impl<B: Backend> ActiveUnitsMetric<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn active_units(&self) -> usize {
+        self.stats
+            .variances()
+            .into_iter()
+            .filter(|variance| *variance > self.threshold)
+            .count()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Metric for ActiveUnitsMetric<B> {
+    type Input = ActiveUnitsInput<B>;
+    const NAME: &'static str = "Active Units";
+
+    fn update(
+        &mut self,
+        input: &Self::Input,
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let dim = input.latent_mean.dims()[1];
+        let values = |tensor: &Tensor<B, 2>| -> Vec<f64> {
+            tensor
+                .clone()
+                .into_data()
+                .value
+                .into_iter()
+                .map(f64::from_elem)
+                .collect()
+        };
+        self.stats.update(
+            values(&input.latent_mean),
+            values(&input.kl_per_dim),
+            dim,
+        );
+
+        let active = self.active_units();
+        let kl = self
+            .stats
+            .kl()
+            .iter()
+            .map(|kl| format!("{kl:.2}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        MetricEntry::new(
+            Self::NAME.to_string(),
+            format!("{}: {active}/{dim} (KL per dim: {kl})", Self::NAME),
+            active.to_string(),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.stats = LatentStats::default();
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Numeric for ActiveUnitsMetric<B> {
+    fn value(&self) -> f64 {
+        self.active_units() as f64
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<Option<SampleQualityInput<B>>> for VAEOutput<B> {
+    fn adapt(&self) -> Option<SampleQualityInput<B>> {
+        self.quality.clone()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<NonFiniteStopInput> for VAEOutput<B> {
+    fn adapt(&self) -> NonFiniteStopInput {
+        NonFiniteStopInput { stop: self.stop }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+pub struct NonFiniteStopInput {
+    stop: bool,
+}
+
+/// Interrupts the learner when a training step asks to stop on a
+/// non-finite loss, and records that it did in `stopped`.
+#[cfg(not(target_family = "wasm"))]
+pub struct NonFiniteStopMetric {
+    interrupter: TrainingInterrupter,
+    stopped: Arc<AtomicBool>,
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl NonFiniteStopMetric {
+    pub fn new(
+        interrupter: TrainingInterrupter,
+        stopped: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            interrupter,
+            stopped,
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl Metric for NonFiniteStopMetric {
+    type Input = NonFiniteStopInput;
+    const NAME: &'static str = "Non-finite Stop";
+
+    fn update(
+        &mut self,
+        input: &Self::Input,
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        if input.stop {
+            self.stopped.store(true, Ordering::Relaxed);
+            self.interrupter.stop();
+        }
+        let stopped = self.stopped.load(Ordering::Relaxed);
+        MetricEntry::new(
+            Self::NAME.to_string(),
+            format!("{}: {stopped}", Self::NAME),
+            (stopped as u8).to_string(),
+        )
+    }
+
+    fn clear(&mut self) {}
+}
+
+/// Points the validation step may still generate for the sample-quality
+/// metrics this epoch. Training steps refill it, so a validation epoch
+/// decodes at most `max` samples. Clones share the count.
+#[derive(Clone, Debug)]
+pub struct SampleBudget {
+    remaining: Arc<AtomicUsize>,
+    max: usize,
+}
+
+constant!(SampleBudget);
+
+impl SampleBudget {
+    pub fn new(max: usize) -> Self {
+        Self {
+            remaining: Arc::new(AtomicUsize::new(max)),
+            max,
+        }
+    }
+
+    pub fn refill(&self) {
+        self.remaining.store(self.max, Ordering::Relaxed);
+    }
+
+    /// Takes up to `n` points, returning how many were granted.
+    pub fn take(&self, n: usize) -> usize {
+        let previous = self
+            .remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                Some(left.saturating_sub(n))
+            })
+            .unwrap_or_else(|left| left);
+        previous.min(n)
+    }
+}
+
+/// Points generated for each label of a batch, next to the true points
+/// and the arms they come from.
+#[derive(Clone)]
+pub struct SampleQualityInput<B: Backend> {
+    generated: Tensor<B, 3>,
+    target: Tensor<B, 3>,
+    labels: Tensor<B, 2>,
+    classes: Tensor<B, 2, Int>,
+    label_mask: Tensor<B, 2>,
+    /// Whether generation was conditioned on the classes rather than
+    /// the labels.
+    categorical: bool,
+}
+
+impl<B: Backend> SampleQualityInput<B> {
+    pub fn new(
+        generated: Tensor<B, 3>,
+        target: Tensor<B, 3>,
+        labels: Tensor<B, 2>,
+        classes: Tensor<B, 2, Int>,
+        label_mask: Tensor<B, 2>,
+    ) -> Self {
+        Self {
+            generated,
+            target,
+            labels,
+            classes,
+            label_mask,
+            categorical: false,
+        }
+    }
+
+    pub fn with_categorical(mut self, categorical: bool) -> Self {
+        self.categorical = categorical;
+        self
+    }
+}
+
+/// Compares generated and true points with `D`, globally and within
+/// `n_bins` label bins. Points accumulate over the epoch (up to
+/// `max_points`) and the distance is computed once, on the last batch.
+/// Earlier batches repeat the previous epoch's value, or report the
+/// running value during the first epoch.
+pub struct SampleQualityMetric<B: Backend, D: SampleDistance> {
+    distance: D,
+    n_bins: usize,
+    max_points: usize,
+    generated: Vec<Point>,
+    target: Vec<Point>,
+    labels: Vec<f32>,
+    value: f64,
+    bins: String,
+    /// Whether an epoch has ended, so `value` covers a whole epoch.
+    complete: bool,
+    _b: B,
+}
+
+pub type MMDMetric<B> = SampleQualityMetric<B, MMD>;
+pub type ChamferMetric<B> = SampleQualityMetric<B, Chamfer>;
+pub type SlicedWassersteinMetric<B> =
+    SampleQualityMetric<B, SlicedWasserstein>;
+
+impl<B: Backend, D: SampleDistance> Default for SampleQualityMetric<B, D> {
+    fn default() -> Self {
+        Self {
+            distance: D::default(),
+            n_bins: 4,
+            max_points: 1024,
+            generated: Vec::new(),
+            target: Vec::new(),
+            labels: Vec::new(),
+            value: f64::NAN,
+            bins: String::new(),
+            complete: false,
+            _b: B::default(),
+        }
+    }
+}
+
+impl<B: Backend, D: SampleDistance> SampleQualityMetric<B, D> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_distance(mut self, distance: D) -> Self {
+        self.distance = distance;
+        self
+    }
+
+    pub fn with_bins(mut self, n_bins: usize) -> Self {
+        self.n_bins = n_bins;
+        self
+    }
+
+    pub fn with_max_points(mut self, max_points: usize) -> Self {
+        self.max_points = max_points;
+        self
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend, D: SampleDistance> Metric for SampleQualityMetric<B, D> {
+    type Input = Option<SampleQualityInput<B>>;
+    const NAME: &'static str = D::NAME;
+
+    fn update(
+        &mut self,
+        input: &Self::Input,
+        metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let room = self.max_points.saturating_sub(self.labels.len());
+        if let Some(input) = input.as_ref().filter(|_| room > 0) {
+            let flatten = |tensor: Tensor<B, 2>| -> Vec<f32> {
+                tensor.to_vec().into_iter().flatten().collect()
+            };
+            let labeled = flatten(input.label_mask.clone())
+                .into_iter()
+                .map(|mask| mask > 0.5);
+            input
+                .generated
+                .clone()
+                .to_points()
+                .into_iter()
+                .zip(input.target.clone().to_points())
+                .zip(flatten(input.labels.clone()))
+                .zip(labeled)
+                .filter(|(_, labeled)| *labeled)
+                .take(room)
+                .for_each(|(((generated, target), label), _)| {
+                    self.generated.push(generated);
+                    self.target.push(target);
+                    self.labels.push(label);
+                });
+        }
+
+        let progress = &metadata.progress;
+        let last = progress.items_processed >= progress.items_total;
+        if (last || !self.complete) && !self.generated.is_empty() {
+            self.complete |= last;
+            self.value =
+                self.distance.distance(&self.generated, &self.target);
+            self.bins = crate::quality::binned(
+                &self.distance,
+                &self.generated,
+                &self.target,
+                &self.labels,
+                self.n_bins,
+            )
+            .iter()
+            .map(|(start, value)| format!("t>={start:.1}: {value:.3}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        }
+
+        MetricEntry::new(
+            Self::NAME.to_string(),
+            format!("{}: {:.3} ({})", Self::NAME, self.value, self.bins),
+            self.value.to_string(),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.generated.clear();
+        self.target.clear();
+        self.labels.clear();
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend, D: SampleDistance> Numeric for SampleQualityMetric<B, D> {
+    fn value(&self) -> f64 {
+        self.value
+    }
+}
+
+/// Mean distance from each generated point to the data it was conditioned
+/// on: the slice of its arm at the requested label, or the whole arm of
+/// the requested class for categorical models.
+pub struct ConditionalFidelityMetric<B: Backend> {
+    source: DataSource,
+    #[cfg(not(target_family = "wasm"))]
+    state: NumericMetricState,
+    _b: B,
+}
+
+impl<B: Backend> Default for ConditionalFidelityMetric<B> {
+    fn default() -> Self {
+        Self {
+            source: DataSource::SwissRoll,
+            #[cfg(not(target_family = "wasm"))]
+            state: NumericMetricState::default(),
+            _b: B::default(),
+        }
+    }
+}
+
+impl<B: Backend> ConditionalFidelityMetric<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_source(mut self, source: DataSource) -> Self {
+        self.source = source;
+        self
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Metric for ConditionalFidelityMetric<B> {
+    type Input = Option<SampleQualityInput<B>>;
+    const NAME: &'static str = "Conditional Fidelity";
+
+    fn update(
+        &mut self,
+        input: &Self::Input,
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let Some(input) = input else {
+            return self.state.update(
+                self.state.value(),
+                0,
+                FormatOptions::new(Self::NAME).precision(3),
+            );
+        };
+        let flatten = |tensor: Tensor<B, 2>| -> Vec<f32> {
+            tensor.to_vec().into_iter().flatten().collect()
+        };
+        let classes = input
+            .classes
+            .clone()
+            .into_data()
+            .convert::<i64>()
+            .value
+            .into_iter()
+            .map(|class| class as usize);
+        let distances = input
+            .generated
+            .clone()
+            .to_points()
+            .into_iter()
+            .zip(flatten(input.labels.clone()))
+            .zip(classes)
+            .zip(flatten(input.label_mask.clone()))
+            .filter(|(_, mask)| *mask > 0.5)
+            .map(|(((point, t), class), _)| match input.categorical {
+                true => self.source.arm_distance(&point, class),
+                false => self.source.arm_slice_distance(&point, t, class),
+            })
+            .collect::<Vec<_>>();
+        let n = distances.len();
+        let distance = distances.into_iter().map(f64::from).sum::<f64>()
+            / n.max(1) as f64;
+
+        self.state.update(
+            distance,
+            n.max(1),
+            FormatOptions::new(Self::NAME).precision(3),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.state.reset();
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Numeric for ConditionalFidelityMetric<B> {
+    fn value(&self) -> f64 {
+        self.state.value()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<LossInput<B>> for VQVAEOutput<B> {
+    fn adapt(&self) -> LossInput<B> {
+        LossInput::new(
+            self.recon_loss.clone() + self.commitment_loss.clone(),
+        )
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<LossInput<B>> for FlowOutput<B> {
+    fn adapt(&self) -> LossInput<B> {
+        LossInput::new(self.nll.clone())
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<LossInput<B>> for DiffusionOutput<B> {
+    fn adapt(&self) -> LossInput<B> {
+        LossInput::new(self.loss.clone())
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<ReconstructionLossInput<B>> for VQVAEOutput<B> {
+    fn adapt(&self) -> ReconstructionLossInput<B> {
+        ReconstructionLossInput::new(self.recon_loss.clone())
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<CodebookPerplexityInput<B>> for VQVAEOutput<B> {
+    fn adapt(&self) -> CodebookPerplexityInput<B> {
+        CodebookPerplexityInput::new(self.perplexity.clone())
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Adaptor<CodebookUsageInput<B>> for VQVAEOutput<B> {
+    fn adapt(&self) -> CodebookUsageInput<B> {
+        CodebookUsageInput::new(self.usage.clone())
+    }
+}
+
+pub struct CodebookPerplexityInput<B: Backend> {
+    tensor: Tensor<B, 1>,
+}
+
+impl<B: Backend> CodebookPerplexityInput<B> {
+    pub fn new(tensor: Tensor<B, 1>) -> Self {
+        Self { tensor }
+    }
+}
+
+#[derive(Default)]
+pub struct CodebookPerplexityMetric<B: Backend> {
+    #[cfg(not(target_family = "wasm"))]
+    state: NumericMetricState,
+    _b: B,
+}
+
+impl<B: Backend> CodebookPerplexityMetric<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Metric for CodebookPerplexityMetric<B> {
+    type Input = CodebookPerplexityInput<B>;
+    const NAME: &'static str = "Codebook Perplexity";
+
+    fn update(
+        &mut self,
+        perplexity: &Self::Input,
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let perplexity = f64::from_elem(
+            perplexity.tensor.clone().mean().into_data().value[0],
+        );
+        self.state.update(
+            perplexity,
+            1,
+            FormatOptions::new(Self::NAME).precision(2),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.state.reset();
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Numeric for CodebookPerplexityMetric<B> {
+    fn value(&self) -> f64 {
+        self.state.value()
+    }
+}
+
+pub struct CodebookUsageInput<B: Backend> {
+    tensor: Tensor<B, 1>,
+}
+
+impl<B: Backend> CodebookUsageInput<B> {
+    pub fn new(tensor: Tensor<B, 1>) -> Self {
+        Self { tensor }
+    }
+}
+
+#[derive(Default)]
+pub struct CodebookUsageMetric<B: Backend> {
+    #[cfg(not(target_family = "wasm"))]
+    state: NumericMetricState,
+    _b: B,
+}
+
+impl<B: Backend> CodebookUsageMetric<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Metric for CodebookUsageMetric<B> {
+    type Input = CodebookUsageInput<B>;
+    const NAME: &'static str = "Codebook Usage";
+
+    fn update(
+        &mut self,
+        usage: &Self::Input,
+        _metadata: &MetricMetadata,
+    ) -> MetricEntry {
+        let usage = f64::from_elem(
+            usage.tensor.clone().mean().into_data().value[0],
+        );
+        self.state.update(
+            100. * usage,
+            1,
+            FormatOptions::new(Self::NAME).unit("%").precision(1),
+        )
+    }
+
+    fn clear(&mut self) {
+        self.state.reset();
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> Numeric for CodebookUsageMetric<B> {
+    fn value(&self) -> f64 {
+        self.state.value()
+    }
+}
+
+#[cfg(all(test, not(target_family = "wasm")))]
+mod tests {
+    use super::*;
+    use burn::{backend::NdArray, data::dataloader::Progress};
+
+    type TestBackend = NdArray<f32>;
+
+    const T: f32 = 6.;
+
+    fn metadata() -> MetricMetadata {
+        MetricMetadata {
+            progress: Progress {
+                items_processed: 1,
+                items_total: 1,
+            },
+            epoch: 1,
+            epoch_total: 1,
+            iteration: 1,
+            lr: None,
+        }
+    }
+
+    /// Two samples on the first of two arms at label `T`, the first
+    /// requested from arm 0 and the second from arm 1.
+    fn fidelity(labels: [f32; 2], categorical: bool) -> f64 {
+        let point = [T * T.cos(), 10., T * T.sin()];
+        let generated =
+            Tensor::<TestBackend, 2>::from_floats([point, point])
+                .reshape([2, 1, 3]);
+        let input = SampleQualityInput::new(
+            generated.clone(),
+            generated,
+            Tensor::from_floats([[labels[0]], [labels[1]]]),
+            Tensor::from_ints([[0], [1]]),
+            Tensor::ones([2, 1]),
+        )
+        .with_categorical(categorical);
+
+        let mut metric = ConditionalFidelityMetric::<TestBackend>::new()
+            .with_source(DataSource::Spirals(2));
+        metric.update(&Some(input), &metadata());
+        metric.value()
+    }
+
+    #[test]
+    fn categorical_fidelity_measures_the_requested_arm() {
+        // Labels are ignored by categorical models, so only the sample
+        // requested from the other arm is penalised.
+        let point = [T * T.cos(), 10., T * T.sin()];
+        let [own, other] = [0, 1].map(|arm| {
+            DataSource::Spirals(2).arm_distance(&point, arm) as f64
+        });
+        let fidelity = fidelity([0., 0.], true);
+
+        assert!(own < 0.05 && other > 1.);
+        assert!((fidelity - (own + other) / 2.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn continuous_fidelity_penalises_the_wrong_arm() {
+        // The opposite arm at the same label is 2T away.
+        let fidelity = fidelity([T, T], false);
+
+        assert!((fidelity - T as f64).abs() < 1e-3);
+    }
+}
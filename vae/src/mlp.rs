@@ -1,21 +1,200 @@
 use burn::{
     config::Config,
-    module::Module,
+    constant,
+    module::{Module, Param},
     nn::{
-        Dropout, DropoutConfig, GELU, LayerNorm, LayerNormConfig, Linear,
-        LinearConfig,
+        BatchNorm, BatchNormConfig, Dropout, DropoutConfig, LayerNorm,
+        LayerNormConfig, Linear, LinearConfig,
     },
-    tensor::{Tensor, backend::Backend},
+    tensor::{Tensor, activation, backend::Backend},
 };
 
+#[derive(Config, Debug)]
+pub enum Activation {
+    ReLU,
+    GELU,
+    SiLU,
+    Tanh,
+    LeakyReLU(f64),
+    Mish,
+}
+
+constant!(Activation);
+
+impl Activation {
+    pub fn forward<B: Backend, const D: usize>(
+        &self,
+        x: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        match self {
+            Self::ReLU => activation::relu(x),
+            Self::GELU => activation::gelu(x),
+            Self::SiLU => x.clone() * activation::sigmoid(x),
+            Self::Tanh => x.tanh(),
+            Self::LeakyReLU(slope) => {
+                x.clone().clamp_min(0.)
+                    + x.clamp_max(0.).mul_scalar(*slope)
+            }
+            Self::Mish => x.clone() * x.exp().log1p().tanh(),
+        }
+    }
+}
+
+#[derive(Config, Debug)]
+pub enum Norm {
+    None,
+    LayerNorm,
+    BatchNorm,
+    RMSNorm,
+}
+
+#[derive(Module, Debug)]
+pub struct RMSNorm<B: Backend> {
+    gamma: Param<Tensor<B, 1>>,
+    epsilon: f64,
+}
+
+#[derive(Config, Debug)]
+pub struct RMSNormConfig {
+    d_model: usize,
+    #[config(default = 1e-5)]
+    epsilon: f64,
+}
+
+impl RMSNormConfig {
+    pub fn init<B: Backend>(&self) -> RMSNorm<B> {
+        RMSNorm {
+            gamma: Param::from(Tensor::ones([self.d_model])),
+            epsilon: self.epsilon,
+        }
+    }
+
+    pub fn init_with<B: Backend>(
+        &self,
+        record: RMSNormRecord<B>,
+    ) -> RMSNorm<B> {
+        RMSNorm {
+            gamma: record.gamma,
+            epsilon: self.epsilon,
+        }
+    }
+}
+
+impl<B: Backend> RMSNorm<B> {
+    pub fn forward<const D: usize>(
+        &self,
+        x: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        let rms = x
+            .clone()
+            .powf(2.)
+            .mean_dim(D - 1)
+            .add_scalar(self.epsilon)
+            .sqrt();
+
+        x.div(rms).mul(self.gamma.val().unsqueeze())
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct Normalization<B: Backend> {
+    layer: Option<LayerNorm<B>>,
+    batch: Option<BatchNorm<B, 1>>,
+    rms: Option<RMSNorm<B>>,
+}
+
+impl<B: Backend> Normalization<B> {
+    pub fn forward<const D: usize>(
+        &self,
+        x: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        match (&self.layer, &self.batch, &self.rms) {
+            (Some(norm), _, _) => norm.forward(x),
+            (_, Some(norm), _) => {
+                norm.forward(x.swap_dims(1, D - 1)).swap_dims(1, D - 1)
+            }
+            (_, _, Some(norm)) => norm.forward(x),
+            _ => x,
+        }
+    }
+}
+
+#[derive(Config, Debug)]
+pub enum Residual {
+    None,
+    /// Adds the input only where a layer keeps its width.
+    Identity,
+    /// Adds the input, projected where a layer changes width.
+    Additive,
+    Gated,
+    Dense,
+}
+
+constant!(Residual);
+
+#[derive(Module, Debug)]
+pub struct MLPLayer<B: Backend> {
+    linear: Linear<B>,
+    norm: Normalization<B>,
+    dropout: Dropout,
+    skip: Option<Linear<B>>,
+    gate: Option<Linear<B>>,
+}
+
+impl<B: Backend> MLPLayer<B> {
+    fn forward<const D: usize>(
+        &self,
+        x: Tensor<B, D>,
+        activation: &Activation,
+        residual: &Residual,
+        pre_norm: bool,
+        film: Option<(Tensor<B, D>, Tensor<B, D>)>,
+    ) -> Tensor<B, D> {
+        let h = match pre_norm {
+            true => {
+                let h = self.linear.forward(self.norm.forward(x.clone()));
+                self.dropout.forward(activation.forward(h))
+            }
+            false => {
+                let h =
+                    self.dropout.forward(self.linear.forward(x.clone()));
+                activation.forward(self.norm.forward(h))
+            }
+        };
+        let h = match film {
+            Some((gamma, beta)) => h * gamma.add_scalar(1.) + beta,
+            None => h,
+        };
+        let skip = |x: Tensor<B, D>| match &self.skip {
+            Some(projection) => projection.forward(x),
+            None => x,
+        };
+
+        match (residual, &self.gate) {
+            (Residual::None, _) => h,
+            (Residual::Identity, _) if h.dims() == x.dims() => h + x,
+            (Residual::Identity, _) => h,
+            (Residual::Additive, _) => h + skip(x),
+            (Residual::Gated, Some(gate)) => {
+                let g = activation::sigmoid(gate.forward(x.clone()));
+                g.clone() * h + g.neg().add_scalar(1.) * skip(x)
+            }
+            (Residual::Gated, None) => h + skip(x),
+            (Residual::Dense, _) => Tensor::cat(vec![x, h], D - 1),
+        }
+    }
+}
+
 #[derive(Module, Debug)]
 pub struct MLPBlock<B: Backend, const D: usize> {
-    layers: Vec<Linear<B>>,
+    layers: Vec<MLPLayer<B>>,
     final_layer: Linear<B>,
+    final_norm: Normalization<B>,
     dropout: Dropout,
-    activation: GELU,
-    norm: LayerNorm<B>,
-    final_norm: LayerNorm<B>,
+    activation: Activation,
+    residual: Residual,
+    pre_norm: bool,
+    activate_output: bool,
 }
 
 #[derive(Config, Debug)]
@@ -26,60 +205,192 @@ pub struct MLPBlockConfig {
     pub output_dim: usize,
     #[config(default = 0.5)]
     dropout: f64,
+    #[config(default = "Activation::GELU")]
+    activation: Activation,
+    #[config(default = "Norm::LayerNorm")]
+    norm: Norm,
+    #[config(default = true)]
+    activate_output: bool,
+    hidden_dims: Option<Vec<usize>>,
+    layer_dropouts: Option<Vec<f64>>,
+    #[config(default = "Residual::Identity")]
+    residual: Residual,
+    #[config(default = false)]
+    pre_norm: bool,
 }
 
 impl<B: Backend, const D: usize> MLPBlock<B, D> {
     pub fn forward(&self, x: Tensor<B, D>) -> Tensor<B, D> {
-        let x = self.layers.iter().fold(x, |x, layer| {
-            let input = x.clone();
-            let mut x = layer.forward(x);
-            x = self.dropout.forward(x);
-            x = self.norm.forward(x);
-            x = self.activation.forward(x);
-            if x.shape() == input.shape() {
-                x = x + input;
-            }
+        self.forward_modulated(x, Vec::new())
+    }
 
-            x
+    pub fn forward_modulated(
+        &self,
+        x: Tensor<B, D>,
+        modulation: Vec<(Tensor<B, D>, Tensor<B, D>)>,
+    ) -> Tensor<B, D> {
+        let mut modulation = modulation.into_iter();
+        let x = self.layers.iter().fold(x, |x, layer| {
+            layer.forward(
+                x,
+                &self.activation,
+                &self.residual,
+                self.pre_norm,
+                modulation.next(),
+            )
         });
 
         let mut x = self.final_layer.forward(x);
         x = self.dropout.forward(x);
         x = self.final_norm.forward(x);
-        self.activation.forward(x)
+        match self.activate_output {
+            true => self.activation.forward(x),
+            false => x,
+        }
     }
 }
 
+struct LayerSpec {
+    input: usize,
+    width: usize,
+    dropout: f64,
+}
+
 impl MLPBlockConfig {
-    fn build_layers(&self) -> Vec<LinearConfig> {
-        (0..self.n_layers - 1).fold(
-            vec![LinearConfig::new(self.input_dim, self.hidden_dim)],
-            |mut layers, _| {
-                layers.push(LinearConfig::new(
-                    self.hidden_dim,
-                    self.hidden_dim,
-                ));
-                layers
+    pub(crate) fn with_input_dim(mut self, input_dim: usize) -> Self {
+        self.input_dim = input_dim;
+        self
+    }
+
+    pub(crate) fn widths(&self) -> Vec<usize> {
+        self.hidden_dims
+            .clone()
+            .unwrap_or_else(|| vec![self.hidden_dim; self.n_layers])
+    }
+
+    /// Widest hidden layer, used as the model size for warmup schedules.
+    pub fn model_size(&self) -> usize {
+        self.widths().into_iter().max().unwrap_or(self.hidden_dim)
+    }
+
+    fn build_layers(&self) -> (Vec<LayerSpec>, usize) {
+        self.widths().into_iter().enumerate().fold(
+            (Vec::new(), self.input_dim),
+            |(mut layers, input), (i, width)| {
+                let dropout = self
+                    .layer_dropouts
+                    .as_ref()
+                    .and_then(|dropouts| dropouts.get(i).copied())
+                    .unwrap_or(self.dropout);
+                layers.push(LayerSpec {
+                    input,
+                    width,
+                    dropout,
+                });
+
+                match self.residual {
+                    Residual::Dense => (layers, input + width),
+                    _ => (layers, width),
+                }
             },
         )
     }
 
+    fn norm_dim(&self, spec: &LayerSpec) -> usize {
+        match self.pre_norm {
+            true => spec.input,
+            false => spec.width,
+        }
+    }
+
+    fn skip(&self, spec: &LayerSpec) -> Option<LinearConfig> {
+        let projects =
+            matches!(self.residual, Residual::Additive | Residual::Gated);
+        (projects && spec.input != spec.width).then(|| {
+            LinearConfig::new(spec.input, spec.width).with_bias(false)
+        })
+    }
+
+    fn gate(&self, spec: &LayerSpec) -> Option<LinearConfig> {
+        matches!(self.residual, Residual::Gated)
+            .then(|| LinearConfig::new(spec.input, spec.width))
+    }
+
+    fn init_layer<B: Backend>(&self, spec: &LayerSpec) -> MLPLayer<B> {
+        MLPLayer {
+            linear: LinearConfig::new(spec.input, spec.width).init(),
+            norm: self.init_norm(self.norm_dim(spec)),
+            dropout: DropoutConfig::new(spec.dropout).init(),
+            skip: self.skip(spec).map(|config| config.init()),
+            gate: self.gate(spec).map(|config| config.init()),
+        }
+    }
+
+    fn init_layer_with<B: Backend>(
+        &self,
+        spec: &LayerSpec,
+        record: MLPLayerRecord<B>,
+    ) -> MLPLayer<B> {
+        MLPLayer {
+            linear: LinearConfig::new(spec.input, spec.width)
+                .init_with(record.linear),
+            norm: self.init_norm_with(self.norm_dim(spec), record.norm),
+            dropout: DropoutConfig::new(spec.dropout).init(),
+            skip: self
+                .skip(spec)
+                .zip(record.skip)
+                .map(|(config, record)| config.init_with(record)),
+            gate: self
+                .gate(spec)
+                .zip(record.gate)
+                .map(|(config, record)| config.init_with(record)),
+        }
+    }
+
+    fn init_norm<B: Backend>(&self, dim: usize) -> Normalization<B> {
+        Normalization {
+            layer: matches!(self.norm, Norm::LayerNorm)
+                .then(|| LayerNormConfig::new(dim).init()),
+            batch: matches!(self.norm, Norm::BatchNorm)
+                .then(|| BatchNormConfig::new(dim).init()),
+            rms: matches!(self.norm, Norm::RMSNorm)
+                .then(|| RMSNormConfig::new(dim).init()),
+        }
+    }
+
+    fn init_norm_with<B: Backend>(
+        &self,
+        dim: usize,
+        record: NormalizationRecord<B>,
+    ) -> Normalization<B> {
+        Normalization {
+            layer: record
+                .layer
+                .map(|record| LayerNormConfig::new(dim).init_with(record)),
+            batch: record
+                .batch
+                .map(|record| BatchNormConfig::new(dim).init_with(record)),
+            rms: record
+                .rms
+                .map(|record| RMSNormConfig::new(dim).init_with(record)),
+        }
+    }
+
     pub fn init<B: Backend, const D: usize>(&self) -> MLPBlock<B, D> {
+        let (layers, final_dim) = self.build_layers();
         MLPBlock {
-            layers: self
-                .build_layers()
-                .into_iter()
-                .map(|config| config.init())
+            layers: layers
+                .iter()
+                .map(|spec| self.init_layer(spec))
                 .collect(),
-            final_layer: LinearConfig::new(
-                self.hidden_dim,
-                self.output_dim,
-            )
-            .init(),
+            final_layer: LinearConfig::new(final_dim, self.output_dim)
+                .init(),
+            final_norm: self.init_norm(self.output_dim),
             dropout: DropoutConfig::new(self.dropout).init(),
-            activation: GELU::new(),
-            norm: LayerNormConfig::new(self.hidden_dim).init(),
-            final_norm: LayerNormConfig::new(self.output_dim).init(),
+            activation: self.activation.clone(),
+            residual: self.residual.clone(),
+            pre_norm: self.pre_norm,
+            activate_output: self.activate_output,
         }
     }
 
@@ -87,24 +398,22 @@ impl MLPBlockConfig {
         &self,
         record: MLPBlockRecord<B, D>,
     ) -> MLPBlock<B, D> {
+        let (layers, final_dim) = self.build_layers();
         MLPBlock {
-            layers: self
-                .build_layers()
-                .into_iter()
+            layers: layers
+                .iter()
                 .zip(record.layers)
-                .map(|(config, layer)| config.init_with(layer))
+                .map(|(spec, layer)| self.init_layer_with(spec, layer))
                 .collect(),
-            final_layer: LinearConfig::new(
-                self.hidden_dim,
-                self.output_dim,
-            )
-            .init_with(record.final_layer),
+            final_layer: LinearConfig::new(final_dim, self.output_dim)
+                .init_with(record.final_layer),
+            final_norm: self
+                .init_norm_with(self.output_dim, record.final_norm),
             dropout: DropoutConfig::new(self.dropout).init(),
-            activation: GELU::new(),
-            norm: LayerNormConfig::new(self.hidden_dim)
-                .init_with(record.norm),
-            final_norm: LayerNormConfig::new(self.output_dim)
-                .init_with(record.final_norm),
+            activation: self.activation.clone(),
+            residual: self.residual.clone(),
+            pre_norm: self.pre_norm,
+            activate_output: self.activate_output,
         }
     }
 }
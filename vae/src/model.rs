@@ -1,21 +1,30 @@
 use crate::{
+    conditioning::{Conditioner, Conditioning},
+    guard::{NonFiniteGuard, Verdict},
+    head::{LabelHead, LabelHeadConfig},
     loss::KLLoss,
-    metric::VAEOutput,
+    metric::{SampleBudget, SampleLosses, SampleQualityInput, VAEOutput},
     mlp::{MLPBlock, MLPBlockConfig},
+    posterior::{Posterior, reparameterize, std},
 };
 use burn::{
     config::Config,
     module::Module,
-    nn::{Linear, LinearConfig, loss::MSELoss, loss::Reduction},
+    nn::{Linear, LinearConfig},
     tensor::{
-        Distribution, Tensor,
+        Data, Distribution, Int, Shape, Tensor,
         backend::{AutodiffBackend, Backend},
     },
 };
-use dataset::{Point, SpiralBatch, ToPoints, ToVec};
+use dataset::{
+    LABEL_DIM, Point, SpiralBatch, T_MAX, T_MIN, ToPoints, ToVec,
+};
 
 #[cfg(not(target_family = "wasm"))]
-use burn::train::{TrainOutput, TrainStep, ValidStep};
+use burn::{
+    optim::GradientsParams,
+    train::{TrainOutput, TrainStep, ValidStep},
+};
 
 type Batches<B> = Tensor<B, 3>;
 
@@ -23,8 +32,13 @@ type Batches<B> = Tensor<B, 3>;
 pub struct VAE<B: Backend> {
     encoder: Encoder<B>,
     decoder: Decoder<B>,
+    conditioner: Conditioner<B>,
+    label_head: Option<LabelHead<B>>,
     pub kl_weight: f64,
+    pub guidance_scale: f64,
     latent_dim: usize,
+    non_finite: NonFiniteGuard,
+    quality_budget: SampleBudget,
 }
 
 #[derive(Config, Debug)]
@@ -35,68 +49,276 @@ pub struct VAEConfig {
     kl_weight: f64,
     #[config(default = 2)]
     latent_dim: usize,
+    #[config(default = "Conditioning::Concat")]
+    conditioning: Conditioning,
+    /// Drops labels for classifier-free guidance. The decoder input then
+    /// gains a null indicator channel after the label embedding.
+    #[config(default = 0.)]
+    label_dropout: f64,
+    /// Default guidance scale when sampling, 1 for no guidance.
+    #[config(default = 1.)]
+    guidance_scale: f64,
+    label_head: Option<LabelHeadConfig>,
+    #[config(default = "NonFiniteGuard::Ignore")]
+    pub non_finite: NonFiniteGuard,
+    /// Labeled validation points generated per epoch for the
+    /// sample-quality metrics.
+    #[config(default = 1024)]
+    quality_points: usize,
 }
 
 impl VAEConfig {
+    /// Whether missing labels can be inferred, which takes a label head
+    /// and a continuous label to infer.
+    pub fn infers_labels(&self) -> bool {
+        self.label_head.is_some() && !self.conditioning.is_categorical()
+    }
+
+    /// Width of the decoder input: the latent, followed by the label
+    /// embedding and, for guided models, the null indicator, unless FiLM
+    /// feeds them to the hidden layers instead.
+    pub fn decoder_input_dim(&self) -> usize {
+        match self.conditioning {
+            Conditioning::FiLM(_) => self.latent_dim,
+            _ => {
+                self.latent_dim
+                    + self.conditioning.dim()
+                    + (self.label_dropout > 0.) as usize
+            }
+        }
+    }
+
+    /// The decoder with its input width derived from the latent and the
+    /// conditioning, whatever `decoder.block_config` was given.
+    fn decoder(&self) -> DecoderConfig {
+        DecoderConfig {
+            block_config: self
+                .decoder
+                .block_config
+                .clone()
+                .with_input_dim(self.decoder_input_dim()),
+            fc: self.decoder.fc.clone(),
+        }
+    }
+
     pub fn init<B: Backend>(&self) -> VAE<B> {
         VAE {
             encoder: self.encoder.init(),
-            decoder: self.decoder.init(),
+            decoder: self.decoder().init(),
+            conditioner: self.conditioning.init(
+                &self.decoder.block_config.widths(),
+                self.label_dropout,
+            ),
+            label_head: self.label_head.as_ref().map(|head| head.init()),
             kl_weight: self.kl_weight,
+            guidance_scale: self.guidance_scale,
             latent_dim: self.latent_dim,
+            non_finite: self.non_finite.clone(),
+            quality_budget: SampleBudget::new(self.quality_points),
         }
     }
 
     pub fn init_with<B: Backend>(&self, record: VAERecord<B>) -> VAE<B> {
         VAE {
             encoder: self.encoder.init_with(record.encoder),
-            decoder: self.decoder.init_with(record.decoder),
+            decoder: self.decoder().init_with(record.decoder),
+            conditioner: self.conditioning.init_with(
+                &self.decoder.block_config.widths(),
+                self.label_dropout,
+                record.conditioner,
+            ),
+            label_head: self
+                .label_head
+                .as_ref()
+                .zip(record.label_head)
+                .map(|(head, record)| head.init_with(record)),
             kl_weight: self.kl_weight,
+            guidance_scale: self.guidance_scale,
             latent_dim: self.latent_dim,
+            non_finite: self.non_finite.clone(),
+            quality_budget: SampleBudget::new(self.quality_points),
         }
     }
 }
 
 impl<B: Backend> VAE<B> {
-    pub fn forward(&self, x: Batches<B>, y: Tensor<B, 2>) -> VAEOutput<B> {
-        let batchsize = x.dims()[0] as i32;
+    /// Number of classes of a categorical model, 0 otherwise.
+    pub fn n_classes(&self) -> usize {
+        self.conditioner.n_classes()
+    }
+
+    pub fn forward(
+        &self,
+        x: Batches<B>,
+        y: Tensor<B, 2>,
+        classes: Tensor<B, 2, Int>,
+        label_mask: Tensor<B, 2>,
+    ) -> VAEOutput<B> {
+        let batchsize = x.dims()[0];
 
         let (mu, logvar) = self.encoder.forward(x.clone());
         let kl_loss = KLLoss::new().forward(mu.clone(), logvar.clone());
+        let kl_per_dim = KLLoss::new().per_dim(mu.clone(), logvar.clone());
+        let kl = kl_per_dim.clone().mean_dim(1).reshape([batchsize]);
+
+        let z = reparameterize(mu.clone(), logvar);
+
+        let (y, label_loss, label_error) =
+            self.infer_labels(x.clone(), y, label_mask.clone());
+        let c = self.conditioner.forward(y, classes);
+        let output = self.decode(z, c);
+
+        let recon = (output - x).powf(2.).mean_dim(2).reshape([batchsize]);
+        let elbo = recon.clone() + kl.clone().mul_scalar(self.kl_weight);
+        let mask = label_mask.reshape([batchsize]);
+
+        VAEOutput::new(
+            recon.clone().mean(),
+            kl_loss.mul_scalar(self.kl_weight),
+            label_loss,
+            masked_mean(elbo.clone(), mask.clone()),
+            masked_mean(elbo, mask.neg().add_scalar(1.)),
+            label_error,
+            SampleLosses::new(
+                recon,
+                kl,
+                kl_per_dim,
+                mu.reshape([batchsize, self.latent_dim]),
+            ),
+        )
+    }
 
-        let std = logvar.exp() / 2;
-        let eps = Tensor::random_like(&std, Distribution::Normal(0., 1.))
-            .to_device(&x.device());
-        let z = mu + eps * std;
+    /// Fills in missing labels with samples from q(y|x). Labeled points
+    /// train the head by likelihood, unlabeled ones through the ELBO plus
+    /// the KL of q(y|x) from a label prior, as in the M2 model of Kingma
+    /// et al. (2014).
+    fn infer_labels(
+        &self,
+        x: Batches<B>,
+        y: Tensor<B, 2>,
+        label_mask: Tensor<B, 2>,
+    ) -> (Batches<B>, Tensor<B, 1>, Tensor<B, 1>) {
+        let batchsize = x.dims()[0];
+        let y = y.reshape([batchsize, 1, LABEL_DIM]);
+        let mask = label_mask
+            .clone()
+            .reshape([batchsize, 1, 1])
+            .repeat(2, LABEL_DIM);
+        let head = match &self.label_head {
+            Some(head) => head,
+            None => {
+                let loss = Tensor::zeros([1]).to_device(&x.device());
+                return (y * mask, loss.clone(), loss);
+            }
+        };
+
+        let (y_mu, y_logvar) = head.forward(x);
+        let y_hat = reparameterize(y_mu.clone(), y_logvar.clone());
+
+        let error = (y.clone() - y_mu.clone()).powf(2.);
+        let nll = (y_logvar.clone()
+            + error.clone() / y_logvar.clone().exp())
+        .mul_scalar(0.5)
+        .sum_dim(2)
+        .reshape([batchsize]);
+        let (prior_mean, prior_var) = label_prior();
+        let prior_kl = ((y_logvar.clone().exp()
+            + y_mu.sub_scalar(prior_mean).powf(2.))
+        .div_scalar(prior_var)
+            - y_logvar)
+            .add_scalar(prior_var.ln() - 1.)
+            .mul_scalar(0.5)
+            .sum_dim(2)
+            .reshape([batchsize]);
+
+        let labeled = label_mask.reshape([batchsize]);
+        let unlabeled = labeled.clone().neg().add_scalar(1.);
+        let error = masked_mean(
+            error.mean_dim(2).reshape([batchsize]),
+            labeled.clone(),
+        );
+        let loss = (nll * labeled).mean().mul_scalar(head.weight)
+            + (prior_kl * unlabeled).mean();
 
-        let y = y.reshape([batchsize, -1, 1]);
-        let z = Tensor::cat(vec![z, y], 2);
+        (
+            y * mask.clone() + y_hat * mask.neg().add_scalar(1.),
+            loss,
+            error,
+        )
+    }
 
-        let output = self.decoder.forward(z);
-        let recon_loss =
-            MSELoss::new().forward(output, x, Reduction::Mean);
+    fn decode(&self, z: Batches<B>, c: Batches<B>) -> Batches<B> {
+        let modulation = self.conditioner.modulation(c.clone());
+        match modulation.is_empty() {
+            true => self.decoder.forward(Tensor::cat(vec![z, c], 2)),
+            false => self.decoder.forward_modulated(z, modulation),
+        }
+    }
 
-        VAEOutput::new(recon_loss, kl_loss.mul_scalar(self.kl_weight))
+    fn sample(&self, c: Batches<B>, guidance_scale: f64) -> Batches<B> {
+        let latent = Tensor::random(
+            [c.dims()[0], 1, self.latent_dim],
+            Distribution::Normal(0., 1.),
+        )
+        .to_device(&c.device());
+
+        let output = self.decode(latent.clone(), c.clone());
+        if guidance_scale == 1. {
+            return output;
+        }
+
+        let unconditional =
+            self.decode(latent, self.conditioner.null_like(c));
+        unconditional.clone()
+            + (output - unconditional).mul_scalar(guidance_scale)
+    }
+
+    /// One sample per row of `y`, for comparing against a batch.
+    pub fn generate_like(
+        &self,
+        y: Tensor<B, 2>,
+        classes: Tensor<B, 2, Int>,
+    ) -> Batches<B> {
+        let batchsize = y.dims()[0];
+        let y = y.reshape([batchsize, 1, LABEL_DIM]);
+        self.sample(
+            self.conditioner.embed(y, classes),
+            self.guidance_scale,
+        )
     }
 
-    fn _generate(
+    pub(crate) fn _generate(
         &self,
         t: f32,
         n: usize,
+        guidance_scale: f64,
         device: &B::Device,
     ) -> Batches<B> {
-        let latent = Tensor::random(
-            [n, 1, self.latent_dim],
-            Distribution::Normal(0., 1.),
-        )
-        .to_device(device);
+        self.conditioner.check_class(None);
         let t = Tensor::from_floats([t])
             .to_device(device)
             .unsqueeze::<3>()
             .repeat(0, n);
+        let classes = Tensor::zeros([n, 1]).to_device(device);
+
+        self.sample(self.conditioner.embed(t, classes), guidance_scale)
+    }
+
+    pub(crate) fn _generate_class(
+        &self,
+        class: usize,
+        n: usize,
+        guidance_scale: f64,
+        device: &B::Device,
+    ) -> Batches<B> {
+        self.conditioner.check_class(Some(class));
+        let t = Tensor::zeros([n, 1, 1]).to_device(device);
+        let classes = Tensor::from_ints([class as i32])
+            .to_device(device)
+            .unsqueeze::<2>()
+            .repeat(0, n);
 
-        let latent = Tensor::cat(vec![latent, t], 2);
-        self.decoder.forward(latent)
+        self.sample(self.conditioner.embed(t, classes), guidance_scale)
     }
 
     #[cfg(not(target_family = "wasm"))]
@@ -106,7 +328,8 @@ impl<B: Backend> VAE<B> {
         n: usize,
         device: &B::Device,
     ) -> Vec<Point> {
-        self._generate(t, n, device).to_points()
+        self._generate(t, n, self.guidance_scale, device)
+            .to_points()
     }
 
     #[cfg(target_family = "wasm")]
@@ -116,17 +339,65 @@ impl<B: Backend> VAE<B> {
         n: usize,
         device: &B::Device,
     ) -> Vec<Point> {
-        self._generate(t, n, device).to_points().await
+        self._generate(t, n, self.guidance_scale, device)
+            .to_points()
+            .await
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn generate_class(
+        &self,
+        class: usize,
+        n: usize,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate_class(class, n, self.guidance_scale, device)
+            .to_points()
     }
 
-    fn _encode(&self, x: Vec<Point>) -> (Batches<B>, Batches<B>) {
-        let x = Tensor::cat(
-            x.into_iter()
-                .map(|pt| Tensor::from_floats(pt).unsqueeze::<3>())
-                .collect(),
-            0,
+    #[cfg(target_family = "wasm")]
+    pub async fn generate_class(
+        &self,
+        class: usize,
+        n: usize,
+        device: &B::Device,
+    ) -> Vec<Point> {
+        self._generate_class(class, n, self.guidance_scale, device)
+            .to_points()
+            .await
+    }
+
+    pub(crate) fn _encode(
+        &self,
+        x: Vec<Point>,
+    ) -> (Batches<B>, Batches<B>) {
+        self.encoder.forward(to_batch(x))
+    }
+
+    pub(crate) fn _predict_label(&self, x: Vec<Point>) -> Batches<B> {
+        let head = self.label_head.as_ref().expect(
+            "Label prediction needs a model trained with a label head",
         );
-        self.encoder.forward(x)
+        head.forward(to_batch(x)).0
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn predict_label(&self, x: Vec<Point>) -> Vec<f32> {
+        self._predict_label(x)
+            .to_vec()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn predict_label(&self, x: Vec<Point>) -> Vec<f32> {
+        self._predict_label(x)
+            .to_vec()
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
     }
 
     #[cfg(not(target_family = "wasm"))]
@@ -143,6 +414,261 @@ impl<B: Backend> VAE<B> {
         let (mu, log_var) = self._encode(x);
         (mu.to_vec().await, log_var.to_vec().await)
     }
+
+    pub(crate) fn _encode_std(
+        &self,
+        x: Vec<Point>,
+    ) -> (Batches<B>, Batches<B>) {
+        let (mu, log_var) = self._encode(x);
+        (mu, std(log_var))
+    }
+
+    pub(crate) fn _encode_latent(
+        &self,
+        x: Vec<Point>,
+        deterministic: bool,
+    ) -> Batches<B> {
+        let (mu, log_var) = self._encode(x);
+        match deterministic {
+            true => mu,
+            false => reparameterize(mu, log_var),
+        }
+    }
+
+    pub(crate) fn _sample_posterior(
+        &self,
+        x: Vec<Point>,
+        n_samples: usize,
+    ) -> Batches<B> {
+        let (mu, log_var) = self._encode(x);
+        reparameterize(
+            mu.repeat(1, n_samples),
+            log_var.repeat(1, n_samples),
+        )
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn encode_std(
+        &self,
+        x: Vec<Point>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let (mu, std) = self._encode_std(x);
+        (mu.to_vec(), std.to_vec())
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn encode_std(
+        &self,
+        x: Vec<Point>,
+    ) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+        let (mu, std) = self._encode_std(x);
+        (mu.to_vec().await, std.to_vec().await)
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn encode_latent(
+        &self,
+        x: Vec<Point>,
+        deterministic: bool,
+    ) -> Vec<Vec<f32>> {
+        self._encode_latent(x, deterministic).to_vec()
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn encode_latent(
+        &self,
+        x: Vec<Point>,
+        deterministic: bool,
+    ) -> Vec<Vec<f32>> {
+        self._encode_latent(x, deterministic).to_vec().await
+    }
+
+    /// Per-point negative ELBO, reconstruction error and KL, decoding
+    /// from the posterior mean so repeated calls agree.
+    pub(crate) fn _score(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+    ) -> (Tensor<B, 1>, Tensor<B, 1>, Tensor<B, 1>) {
+        let x = to_batch::<B>(x);
+        let batchsize = x.dims()[0];
+
+        let (mu, log_var) = self.encoder.forward(x.clone());
+        let kl = KLLoss::new().per_point(mu.clone(), log_var);
+
+        let y = to_labels(labels).reshape([batchsize, 1, LABEL_DIM]);
+        let classes = match classes {
+            Some(classes) => {
+                assert_eq!(
+                    classes.len(),
+                    batchsize,
+                    "Expected one class per point"
+                );
+                classes.iter().for_each(|class| {
+                    self.conditioner.check_class(Some(*class))
+                });
+                to_classes(classes).to_device(&x.device())
+            }
+            None => {
+                self.conditioner.check_class(None);
+                Tensor::zeros([batchsize, 1]).to_device(&x.device())
+            }
+        };
+        let output = self.decode(mu, self.conditioner.embed(y, classes));
+        let recon = (output - x).powf(2.).mean_dim(2).reshape([batchsize]);
+
+        (
+            recon.clone() + kl.clone().mul_scalar(self.kl_weight),
+            recon,
+            kl,
+        )
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    pub fn score(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+    ) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        if x.is_empty() {
+            return Default::default();
+        }
+        let (neg_elbo, recon, kl) = self._score(x, labels, classes);
+        (
+            flatten(neg_elbo.to_vec()),
+            flatten(recon.to_vec()),
+            flatten(kl.to_vec()),
+        )
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn score(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+    ) -> (Vec<f32>, Vec<f32>, Vec<f32>) {
+        if x.is_empty() {
+            return Default::default();
+        }
+        let (neg_elbo, recon, kl) = self._score(x, labels, classes);
+        (
+            flatten(neg_elbo.to_vec().await),
+            flatten(recon.to_vec().await),
+            flatten(kl.to_vec().await),
+        )
+    }
+
+    /// Negative ELBO at the given percentile of a validation set, for
+    /// use as an outlier cut-off with [`VAE::score`].
+    #[cfg(not(target_family = "wasm"))]
+    pub fn calibrate_threshold(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+        percentile: f64,
+    ) -> f32 {
+        threshold(self.score(x, labels, classes).0, percentile)
+    }
+
+    #[cfg(target_family = "wasm")]
+    pub async fn calibrate_threshold(
+        &self,
+        x: Vec<Point>,
+        labels: Vec<f32>,
+        classes: Option<Vec<usize>>,
+        percentile: f64,
+    ) -> f32 {
+        threshold(self.score(x, labels, classes).await.0, percentile)
+    }
+
+    /// Draws `n_samples` latents per point, indexed `[point][sample]`.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn sample_posterior(
+        &self,
+        x: Vec<Point>,
+        n_samples: usize,
+    ) -> Vec<Vec<Vec<f32>>> {
+        if n_samples == 0 || x.is_empty() {
+            return vec![Vec::new(); x.len()];
+        }
+        self._sample_posterior(x, n_samples)
+            .to_vec()
+            .chunks(n_samples)
+            .map(<[_]>::to_vec)
+            .collect()
+    }
+
+    /// Draws `n_samples` latents per point, indexed `[point][sample]`.
+    #[cfg(target_family = "wasm")]
+    pub async fn sample_posterior(
+        &self,
+        x: Vec<Point>,
+        n_samples: usize,
+    ) -> Vec<Vec<Vec<f32>>> {
+        if n_samples == 0 || x.is_empty() {
+            return vec![Vec::new(); x.len()];
+        }
+        self._sample_posterior(x, n_samples)
+            .to_vec()
+            .await
+            .chunks(n_samples)
+            .map(<[_]>::to_vec)
+            .collect()
+    }
+}
+
+fn flatten(x: Vec<Vec<f32>>) -> Vec<f32> {
+    x.into_iter().flatten().collect()
+}
+
+fn threshold(mut scores: Vec<f32>, percentile: f64) -> f32 {
+    scores.sort_by(f32::total_cmp);
+    let rank = (percentile.clamp(0., 100.) / 100.
+        * scores.len().saturating_sub(1) as f64)
+        .round() as usize;
+    scores.get(rank).copied().unwrap_or(f32::NAN)
+}
+
+/// Gaussian p(y) with the mean and variance of labels drawn uniformly
+/// from the training range.
+fn label_prior() -> (f64, f64) {
+    let (min, max) = (T_MIN as f64, T_MAX as f64);
+    ((min + max) / 2., (max - min).powi(2) / 12.)
+}
+
+fn masked_mean<B: Backend>(
+    x: Tensor<B, 1>,
+    mask: Tensor<B, 1>,
+) -> Tensor<B, 1> {
+    (x * mask.clone()).sum() / mask.sum().add_scalar(1e-8)
+}
+
+pub(crate) fn to_batch<B: Backend>(x: Vec<Point>) -> Batches<B> {
+    Tensor::cat(
+        x.into_iter()
+            .map(|pt| Tensor::from_floats(pt).unsqueeze::<3>())
+            .collect(),
+        0,
+    )
+}
+
+pub(crate) fn to_classes<B: Backend>(
+    classes: Vec<usize>,
+) -> Tensor<B, 2, Int> {
+    let n = classes.len();
+    let classes = classes.into_iter().map(|class| class as i64).collect();
+    Tensor::from_data(Data::new(classes, Shape::new([n])).convert())
+        .reshape([n, 1])
+}
+
+pub(crate) fn to_labels<B: Backend>(y: Vec<f32>) -> Tensor<B, 2> {
+    let n = y.len();
+    Tensor::from_data(Data::new(y, Shape::new([n])).convert())
+        .reshape([n, 1])
 }
 
 #[derive(Module, Debug)]
@@ -150,6 +676,7 @@ pub struct Encoder<B: Backend> {
     block: MLPBlock<B, 3>,
     fc_mu: Linear<B>,
     fc_logvar: Linear<B>,
+    posterior: Posterior,
 }
 
 #[derive(Config, Debug)]
@@ -157,6 +684,8 @@ pub struct EncoderConfig {
     pub block_config: MLPBlockConfig,
     fc_mu: LinearConfig,
     fc_logvar: LinearConfig,
+    #[config(default = "Posterior::LogVar")]
+    posterior: Posterior,
 }
 
 impl EncoderConfig {
@@ -165,6 +694,7 @@ impl EncoderConfig {
             block: self.block_config.init(),
             fc_mu: self.fc_mu.init(),
             fc_logvar: self.fc_logvar.init(),
+            posterior: self.posterior.clone(),
         }
     }
 
@@ -176,6 +706,7 @@ impl EncoderConfig {
             block: self.block_config.init_with(record.block),
             fc_mu: self.fc_mu.init_with(record.fc_mu),
             fc_logvar: self.fc_logvar.init_with(record.fc_logvar),
+            posterior: self.posterior.clone(),
         }
     }
 }
@@ -184,7 +715,7 @@ impl<B: Backend> Encoder<B> {
     pub fn forward(&self, input: Batches<B>) -> (Batches<B>, Batches<B>) {
         let x = self.block.forward(input);
         let mu = self.fc_mu.forward(x.clone());
-        let logvar = self.fc_logvar.forward(x.clone());
+        let logvar = self.posterior.log_var(self.fc_logvar.forward(x));
 
         (mu, logvar)
     }
@@ -226,6 +757,15 @@ impl<B: Backend> Decoder<B> {
         let x = self.block.forward(input);
         self.fc.forward(x)
     }
+
+    pub fn forward_modulated(
+        &self,
+        input: Batches<B>,
+        modulation: Vec<(Batches<B>, Batches<B>)>,
+    ) -> Batches<B> {
+        let x = self.block.forward_modulated(input, modulation);
+        self.fc.forward(x)
+    }
 }
 
 #[cfg(not(target_family = "wasm"))]
@@ -233,17 +773,177 @@ impl<B: AutodiffBackend> TrainStep<SpiralBatch<B>, VAEOutput<B>>
     for VAE<B>
 {
     fn step(&self, batch: SpiralBatch<B>) -> TrainOutput<VAEOutput<B>> {
-        let prediction = self.forward(batch.points, batch.labels);
-        let loss =
-            prediction.recon_loss.clone() + prediction.kl_loss.clone();
+        self.quality_budget.refill();
+        let points = batch.points.clone();
+        let prediction = self.forward(
+            batch.points,
+            batch.labels,
+            batch.classes,
+            batch.label_mask,
+        );
 
-        TrainOutput::new(self, loss.backward(), prediction)
+        let loss = prediction.loss();
+        match self.non_finite.check(&loss, &points) {
+            Verdict::Apply => {
+                TrainOutput::new(self, loss.backward(), prediction)
+            }
+            verdict => TrainOutput {
+                grads: GradientsParams::new(),
+                item: prediction.with_stop(verdict == Verdict::Stop),
+            },
+        }
     }
 }
 
 #[cfg(not(target_family = "wasm"))]
 impl<B: Backend> ValidStep<SpiralBatch<B>, VAEOutput<B>> for VAE<B> {
     fn step(&self, batch: SpiralBatch<B>) -> VAEOutput<B> {
-        self.forward(batch.points, batch.labels)
+        let quality = self.quality_input(&batch);
+        let output = self.forward(
+            batch.points,
+            batch.labels,
+            batch.classes,
+            batch.label_mask,
+        );
+        match quality {
+            Some(quality) => output.with_quality(quality),
+            None => output,
+        }
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> VAE<B> {
+    /// Samples for the labeled rows of `batch` while this epoch's
+    /// budget lasts.
+    fn quality_input(
+        &self,
+        batch: &SpiralBatch<B>,
+    ) -> Option<SampleQualityInput<B>> {
+        let labeled = batch
+            .label_mask
+            .clone()
+            .into_data()
+            .convert::<f32>()
+            .value
+            .into_iter()
+            .enumerate()
+            .filter(|(_, mask)| *mask > 0.5)
+            .map(|(i, _)| i as i32)
+            .collect::<Vec<_>>();
+        let n = self.quality_budget.take(labeled.len());
+        if n == 0 {
+            return None;
+        }
+        let rows = Tensor::from_data(
+            Data::new(labeled[..n].to_vec(), Shape::new([n])).convert(),
+        )
+        .to_device(&batch.points.device());
+        let labels = batch.labels.clone().select(0, rows.clone());
+        let classes = batch.classes.clone().select(0, rows.clone());
+
+        Some(
+            SampleQualityInput::new(
+                self.generate_like(labels.clone(), classes.clone()),
+                batch.points.clone().select(0, rows.clone()),
+                labels,
+                classes,
+                batch.label_mask.clone().select(0, rows),
+            )
+            .with_categorical(self.n_classes() > 0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::backend::NdArray;
+    use dataset::INPUT_DIM;
+
+    type TestBackend = NdArray<f32>;
+
+    fn config(posterior: Posterior) -> VAEConfig {
+        VAEConfig::new(
+            EncoderConfig::new(
+                MLPBlockConfig::new(2, 16, INPUT_DIM, 8),
+                LinearConfig::new(8, 2),
+                LinearConfig::new(8, 2),
+            )
+            .with_posterior(posterior),
+            DecoderConfig::new(
+                MLPBlockConfig::new(2, 16, 2 + LABEL_DIM, 8),
+                LinearConfig::new(8, INPUT_DIM),
+            ),
+        )
+    }
+
+    #[test]
+    fn posterior_samples_match_encoded_moments() {
+        const N_SAMPLES: usize = 20_000;
+        TestBackend::seed(0);
+        let points = vec![[1., 2., -1.], [-3., 0.5, 4.]];
+
+        for posterior in [
+            Posterior::LogVar,
+            Posterior::LogStd,
+            Posterior::SoftplusStd(1e-3),
+        ] {
+            let model = config(posterior).init::<TestBackend>();
+            let (mu, log_var) = model._encode(points.clone());
+            let samples =
+                model._sample_posterior(points.clone(), N_SAMPLES);
+
+            let mean = samples.clone().mean_dim(1);
+            let var = (samples - mean.clone()).powf(2.).mean_dim(1);
+            let var_ratio = var / log_var.clone().exp();
+            let z_score = (mean - mu)
+                / std(log_var).div_scalar((N_SAMPLES as f32).sqrt());
+
+            assert!(
+                z_score.abs().max().into_scalar() < 5.,
+                "sample mean drifts from the encoded mu"
+            );
+            assert!(
+                var_ratio.sub_scalar(1.).abs().max().into_scalar() < 0.05,
+                "sample variance drifts from the encoded log-variance"
+            );
+        }
+    }
+
+    #[test]
+    fn decoder_input_width_follows_conditioning() {
+        let device = Default::default();
+        for conditioning in [
+            Conditioning::Concat,
+            Conditioning::Embedding(4),
+            Conditioning::Fourier(3),
+            Conditioning::FiLM(4),
+        ] {
+            // The configured input width is deliberately wrong.
+            let model = VAEConfig::new(
+                config(Posterior::LogVar).encoder,
+                DecoderConfig::new(
+                    MLPBlockConfig::new(2, 16, 1, 8),
+                    LinearConfig::new(8, INPUT_DIM),
+                ),
+            )
+            .with_conditioning(conditioning)
+            .with_label_dropout(0.2)
+            .init::<TestBackend>();
+            let samples = model._generate(5., 4, 2., &device);
+
+            assert_eq!(samples.dims(), [4, 1, INPUT_DIM]);
+        }
+    }
+
+    #[test]
+    fn softplus_std_stays_finite_for_large_outputs() {
+        let raw = Tensor::<TestBackend, 1>::from_floats([-100., 0., 100.]);
+        let log_var =
+            Posterior::SoftplusStd(1e-3).log_var(raw).into_data().value;
+
+        assert!(log_var.iter().all(|x| x.is_finite()));
+        assert!((log_var[2] - 2. * 100f32.ln()).abs() < 1e-3);
     }
 }
@@ -0,0 +1,49 @@
+use burn::{
+    config::Config,
+    constant,
+    tensor::{Distribution, Tensor, backend::Backend},
+};
+
+/// How the encoder's scale output maps to the posterior log-variance.
+#[derive(Config, Debug)]
+pub enum Posterior {
+    LogVar,
+    LogStd,
+    SoftplusStd(f64),
+}
+
+constant!(Posterior);
+
+impl Posterior {
+    pub fn log_var<B: Backend, const D: usize>(
+        &self,
+        raw: Tensor<B, D>,
+    ) -> Tensor<B, D> {
+        match self {
+            Self::LogVar => raw,
+            Self::LogStd => raw.mul_scalar(2.),
+            Self::SoftplusStd(min) => {
+                softplus(raw).add_scalar(*min).log().mul_scalar(2.)
+            }
+        }
+    }
+}
+
+/// `ln(1 + e^x)`, computed without overflowing for large `x`.
+fn softplus<B: Backend, const D: usize>(x: Tensor<B, D>) -> Tensor<B, D> {
+    x.clone().clamp_min(0.) + x.abs().neg().exp().log1p()
+}
+
+pub fn std<B: Backend, const D: usize>(
+    log_var: Tensor<B, D>,
+) -> Tensor<B, D> {
+    log_var.mul_scalar(0.5).exp()
+}
+
+pub fn reparameterize<B: Backend, const D: usize>(
+    mu: Tensor<B, D>,
+    log_var: Tensor<B, D>,
+) -> Tensor<B, D> {
+    let eps = Tensor::random_like(&mu, Distribution::Normal(0., 1.));
+    mu + eps * std(log_var)
+}
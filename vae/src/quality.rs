@@ -0,0 +1,231 @@
+//! Distances between generated and reference point clouds, computed on
+//! the CPU so they work with any backend.
+use dataset::Point;
+
+pub trait SampleDistance: Default + Send + Sync {
+    const NAME: &'static str;
+
+    fn distance(&self, generated: &[Point], target: &[Point]) -> f64;
+}
+
+fn sq_dist(a: &Point, b: &Point) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (*a as f64 - *b as f64).powi(2))
+        .sum()
+}
+
+/// Median pairwise squared distance of the pooled samples.
+fn median_bandwidth(a: &[Point], b: &[Point]) -> f64 {
+    let pooled = a.iter().chain(b).collect::<Vec<_>>();
+    let mut dists = pooled
+        .iter()
+        .enumerate()
+        .flat_map(|(i, p)| pooled[i + 1..].iter().map(|q| sq_dist(p, q)))
+        .collect::<Vec<_>>();
+    if dists.is_empty() {
+        return 1.;
+    }
+    dists.sort_by(f64::total_cmp);
+    dists[dists.len() / 2].max(1e-12)
+}
+
+/// Squared maximum mean discrepancy with an RBF kernel, bandwidth from
+/// the median heuristic when `bandwidth` is `None`.
+pub fn mmd(a: &[Point], b: &[Point], bandwidth: Option<f64>) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return f64::NAN;
+    }
+    let bandwidth = bandwidth.unwrap_or_else(|| median_bandwidth(a, b));
+    let kernel = |x: &[Point], y: &[Point]| {
+        x.iter()
+            .flat_map(|p| y.iter().map(move |q| (p, q)))
+            .map(|(p, q)| (-sq_dist(p, q) / bandwidth).exp())
+            .sum::<f64>()
+            / (x.len() * y.len()) as f64
+    };
+
+    kernel(a, a) + kernel(b, b) - 2. * kernel(a, b)
+}
+
+/// Symmetric Chamfer distance: mean distance to the nearest neighbour in
+/// the other set, averaged over both directions.
+pub fn chamfer(a: &[Point], b: &[Point]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return f64::NAN;
+    }
+    let nearest = |x: &[Point], y: &[Point]| {
+        x.iter()
+            .map(|p| {
+                y.iter()
+                    .map(|q| sq_dist(p, q))
+                    .fold(f64::INFINITY, f64::min)
+                    .sqrt()
+            })
+            .sum::<f64>()
+            / x.len() as f64
+    };
+
+    0.5 * (nearest(a, b) + nearest(b, a))
+}
+
+/// Evenly spread unit vectors on the sphere (Fibonacci lattice).
+fn directions(n: usize) -> Vec<Point> {
+    let golden = std::f64::consts::PI * (3. - 5f64.sqrt());
+    (0..n)
+        .map(|i| {
+            let z = 1. - 2. * (i as f64 + 0.5) / n as f64;
+            let r = (1. - z * z).sqrt();
+            let phi = golden * i as f64;
+            [(r * phi.cos()) as f32, (r * phi.sin()) as f32, z as f32]
+        })
+        .collect()
+}
+
+fn quantiles(mut x: Vec<f64>, n: usize) -> Vec<f64> {
+    x.sort_by(f64::total_cmp);
+    (0..n)
+        .map(|i| {
+            let pos = (i as f64 + 0.5) / n as f64 * x.len() as f64;
+            x[(pos as usize).min(x.len() - 1)]
+        })
+        .collect()
+}
+
+/// Sliced 1-Wasserstein distance over `n_projections` fixed directions.
+pub fn sliced_wasserstein(
+    a: &[Point],
+    b: &[Point],
+    n_projections: usize,
+) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return f64::NAN;
+    }
+    let n = a.len().max(b.len());
+    let project = |x: &[Point], dir: &Point| {
+        x.iter()
+            .map(|p| p.iter().zip(dir).map(|(p, d)| (p * d) as f64).sum())
+            .collect::<Vec<f64>>()
+    };
+
+    directions(n_projections)
+        .iter()
+        .map(|dir| {
+            quantiles(project(a, dir), n)
+                .into_iter()
+                .zip(quantiles(project(b, dir), n))
+                .map(|(a, b)| (a - b).abs())
+                .sum::<f64>()
+                / n as f64
+        })
+        .sum::<f64>()
+        / n_projections.max(1) as f64
+}
+
+#[derive(Default)]
+pub struct MMD {
+    pub bandwidth: Option<f64>,
+}
+
+impl SampleDistance for MMD {
+    const NAME: &'static str = "MMD";
+
+    fn distance(&self, generated: &[Point], target: &[Point]) -> f64 {
+        mmd(generated, target, self.bandwidth)
+    }
+}
+
+#[derive(Default)]
+pub struct Chamfer;
+
+impl SampleDistance for Chamfer {
+    const NAME: &'static str = "Chamfer Distance";
+
+    fn distance(&self, generated: &[Point], target: &[Point]) -> f64 {
+        chamfer(generated, target)
+    }
+}
+
+pub struct SlicedWasserstein {
+    pub n_projections: usize,
+}
+
+impl Default for SlicedWasserstein {
+    fn default() -> Self {
+        Self { n_projections: 64 }
+    }
+}
+
+impl SampleDistance for SlicedWasserstein {
+    const NAME: &'static str = "Sliced Wasserstein";
+
+    fn distance(&self, generated: &[Point], target: &[Point]) -> f64 {
+        sliced_wasserstein(generated, target, self.n_projections)
+    }
+}
+
+/// Distance from `point` to the nearest reference point whose label is
+/// within `tolerance` of `t`, for data without an analytic slice.
+pub fn nearest_slice_distance(
+    point: &Point,
+    t: f32,
+    reference: &[Point],
+    labels: &[f32],
+    tolerance: f32,
+) -> f64 {
+    reference
+        .iter()
+        .zip(labels)
+        .filter(|(_, label)| (*label - t).abs() <= tolerance)
+        .map(|(q, _)| sq_dist(point, q))
+        .fold(f64::INFINITY, f64::min)
+        .sqrt()
+}
+
+/// Distance from `point` to the nearest reference point of `class`.
+pub fn nearest_class_distance(
+    point: &Point,
+    class: usize,
+    reference: &[Point],
+    classes: &[usize],
+) -> f64 {
+    reference
+        .iter()
+        .zip(classes)
+        .filter(|(_, c)| **c == class)
+        .map(|(q, _)| sq_dist(point, q))
+        .fold(f64::INFINITY, f64::min)
+        .sqrt()
+}
+
+/// Splits paired samples into `n_bins` equal-width bins of their labels
+/// and measures the distance within each, returning `(bin start, value)`.
+pub fn binned<D: SampleDistance>(
+    distance: &D,
+    generated: &[Point],
+    target: &[Point],
+    labels: &[f32],
+    n_bins: usize,
+) -> Vec<(f32, f64)> {
+    let n_bins = n_bins.max(1);
+    let (lo, hi) = labels
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), t| {
+            (lo.min(*t), hi.max(*t))
+        });
+    let width = ((hi - lo) / n_bins.max(1) as f32).max(f32::EPSILON);
+    let bin = |t: f32| (((t - lo) / width) as usize).min(n_bins - 1);
+
+    (0..n_bins)
+        .map(|i| {
+            let (g, x): (Vec<Point>, Vec<Point>) = generated
+                .iter()
+                .zip(target)
+                .zip(labels)
+                .filter(|(_, t)| bin(**t) == i)
+                .map(|((g, x), _)| (*g, *x))
+                .unzip();
+            (lo + i as f32 * width, distance.distance(&g, &x))
+        })
+        .collect()
+}
@@ -0,0 +1,329 @@
+use crate::{
+    metric::VQVAEOutput,
+    mlp::{MLPBlock, MLPBlockConfig},
+    model::{Decoder, DecoderConfig, to_batch},
+};
+use burn::{
+    config::Config,
+    module::{Module, RunningState},
+    nn::loss::{MSELoss, Reduction},
+    tensor::{
+        Distribution, Int, Tensor,
+        backend::{AutodiffBackend, Backend},
+    },
+};
+use dataset::{Point, SpiralBatch};
+
+#[cfg(not(target_family = "wasm"))]
+use burn::train::{TrainOutput, TrainStep, ValidStep};
+
+type Batches<B> = Tensor<B, 3>;
+
+#[derive(Module, Debug)]
+pub struct Codebook<B: Backend> {
+    embeddings: RunningState<Tensor<B, 2>>,
+    cluster_size: RunningState<Tensor<B, 1>>,
+    embed_sum: RunningState<Tensor<B, 2>>,
+    n_codes: usize,
+    code_dim: usize,
+    decay: f64,
+    epsilon: f64,
+    restart_threshold: f64,
+}
+
+#[derive(Config, Debug)]
+pub struct CodebookConfig {
+    pub n_codes: usize,
+    pub code_dim: usize,
+    #[config(default = 0.99)]
+    decay: f64,
+    #[config(default = 1e-5)]
+    epsilon: f64,
+    #[config(default = 1e-2)]
+    restart_threshold: f64,
+}
+
+impl CodebookConfig {
+    pub fn init<B: Backend>(&self) -> Codebook<B> {
+        let embeddings = Tensor::random(
+            [self.n_codes, self.code_dim],
+            Distribution::Normal(0., 1.),
+        );
+
+        Codebook {
+            embeddings: RunningState::new(embeddings.clone()),
+            cluster_size: RunningState::new(Tensor::ones([self.n_codes])),
+            embed_sum: RunningState::new(embeddings),
+            n_codes: self.n_codes,
+            code_dim: self.code_dim,
+            decay: self.decay,
+            epsilon: self.epsilon,
+            restart_threshold: self.restart_threshold,
+        }
+    }
+
+    pub fn init_with<B: Backend>(
+        &self,
+        record: CodebookRecord<B>,
+    ) -> Codebook<B> {
+        self.init().load_record(record)
+    }
+}
+
+pub struct Quantized<B: Backend> {
+    pub quantized: Tensor<B, 2>,
+    pub commitment_loss: Tensor<B, 1>,
+    pub perplexity: Tensor<B, 1>,
+    pub usage: Tensor<B, 1>,
+}
+
+impl<B: Backend> Codebook<B> {
+    pub fn forward(&self, z: Tensor<B, 2>) -> Quantized<B> {
+        let embeddings = self.embeddings.value();
+        let one_hot =
+            self.one_hot(self.nearest(z.clone(), embeddings.clone()));
+        let quantized = one_hot.clone().matmul(embeddings);
+
+        if B::ad_enabled() {
+            self.update(z.clone().detach(), one_hot.clone());
+        }
+
+        let commitment_loss = MSELoss::new().forward(
+            z.clone(),
+            quantized.clone().detach(),
+            Reduction::Mean,
+        );
+        // Straight-through estimator: the decoder sees the quantized
+        // codes while gradients flow back to the encoder unchanged.
+        let quantized = z.clone() + (quantized - z).detach();
+
+        let probs = one_hot.mean_dim(0);
+        let perplexity = (probs.clone()
+            * probs.clone().add_scalar(1e-10).log())
+        .sum()
+        .neg()
+        .exp();
+        let usage = probs.greater_elem(0.).float().mean();
+
+        Quantized {
+            quantized,
+            commitment_loss,
+            perplexity,
+            usage,
+        }
+    }
+
+    pub fn quantize(&self, z: Tensor<B, 2>) -> Tensor<B, 2> {
+        let embeddings = self.embeddings.value();
+        self.one_hot(self.nearest(z, embeddings.clone()))
+            .matmul(embeddings)
+    }
+
+    pub fn sample(&self, n: usize, device: &B::Device) -> Tensor<B, 2> {
+        let logits = self
+            .cluster_size
+            .value()
+            .add_scalar(self.epsilon)
+            .log()
+            .reshape([1, self.n_codes])
+            .repeat(0, n);
+        let gumbel = Tensor::random(
+            [n, self.n_codes],
+            Distribution::Uniform(0., 1.),
+        )
+        .to_device(device)
+        .clamp_min(1e-10)
+        .log()
+        .neg()
+        .log()
+        .neg();
+
+        self.one_hot((logits + gumbel).argmax(1))
+            .matmul(self.embeddings.value())
+    }
+
+    fn nearest(
+        &self,
+        z: Tensor<B, 2>,
+        embeddings: Tensor<B, 2>,
+    ) -> Tensor<B, 2, Int> {
+        let n = z.dims()[0];
+        let z_sq = z.clone().powf(2.).sum_dim(1).repeat(1, self.n_codes);
+        let e_sq = embeddings
+            .clone()
+            .powf(2.)
+            .sum_dim(1)
+            .reshape([1, self.n_codes])
+            .repeat(0, n);
+        let dist =
+            z_sq + e_sq - z.matmul(embeddings.transpose()).mul_scalar(2.);
+
+        dist.argmin(1)
+    }
+
+    fn one_hot(&self, codes: Tensor<B, 2, Int>) -> Tensor<B, 2> {
+        let n = codes.dims()[0];
+        let classes = Tensor::<B, 1, Int>::arange(0..self.n_codes)
+            .to_device(&codes.device())
+            .reshape([1, self.n_codes])
+            .repeat(0, n);
+
+        codes.repeat(1, self.n_codes).equal(classes).float()
+    }
+
+    fn update(&self, z: Tensor<B, 2>, one_hot: Tensor<B, 2>) {
+        let (n, decay, device) = (z.dims()[0], self.decay, z.device());
+        let [k, d] = [self.n_codes, self.code_dim];
+
+        let counts = one_hot.clone().sum_dim(0).reshape([k]);
+        let sums = one_hot.transpose().matmul(z.clone());
+        let cluster_size = self.cluster_size.value().mul_scalar(decay)
+            + counts.mul_scalar(1. - decay);
+        let embed_sum = self.embed_sum.value().mul_scalar(decay)
+            + sums.mul_scalar(1. - decay);
+
+        // Laplace smoothing keeps rarely used codes from dividing by 0.
+        let total = cluster_size.clone().sum().repeat(0, k);
+        let smoothed = cluster_size.clone().add_scalar(self.epsilon)
+            / total.clone().add_scalar(k as f64 * self.epsilon)
+            * total;
+        let embeddings =
+            embed_sum.clone() / smoothed.reshape([k, 1]).repeat(1, d);
+
+        // Dead codes are restarted from random encoder outputs.
+        let dead = cluster_size.clone().lower_elem(self.restart_threshold);
+        let restart = z.select(
+            0,
+            Tensor::<B, 1>::random(
+                [k],
+                Distribution::Uniform(0., n as f64),
+            )
+            .to_device(&device)
+            .int(),
+        );
+        let dead_rows = dead.clone().reshape([k, 1]).repeat(1, d);
+
+        self.embeddings.update(
+            embeddings.mask_where(dead_rows.clone(), restart.clone()),
+        );
+        self.embed_sum
+            .update(embed_sum.mask_where(dead_rows, restart));
+        self.cluster_size.update(cluster_size.mask_fill(dead, 1.));
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct VQVAE<B: Backend> {
+    encoder: MLPBlock<B, 3>,
+    codebook: Codebook<B>,
+    decoder: Decoder<B>,
+    pub commitment_weight: f64,
+}
+
+#[derive(Config, Debug)]
+pub struct VQVAEConfig {
+    pub encoder: MLPBlockConfig,
+    pub codebook: CodebookConfig,
+    decoder: DecoderConfig,
+    #[config(default = 0.25)]
+    commitment_weight: f64,
+}
+
+impl VQVAEConfig {
+    pub fn init<B: Backend>(&self) -> VQVAE<B> {
+        VQVAE {
+            encoder: self.encoder.init(),
+            codebook: self.codebook.init(),
+            decoder: self.decoder.init(),
+            commitment_weight: self.commitment_weight,
+        }
+    }
+
+    pub fn init_with<B: Backend>(
+        &self,
+        record: VQVAERecord<B>,
+    ) -> VQVAE<B> {
+        VQVAE {
+            encoder: self.encoder.init_with(record.encoder),
+            codebook: self.codebook.init_with(record.codebook),
+            decoder: self.decoder.init_with(record.decoder),
+            commitment_weight: self.commitment_weight,
+        }
+    }
+}
+
+impl<B: Backend> VQVAE<B> {
+    pub fn forward(
+        &self,
+        x: Batches<B>,
+        y: Tensor<B, 2>,
+    ) -> VQVAEOutput<B> {
+        let batchsize = x.dims()[0] as i32;
+
+        let z = self.encoder.forward(x.clone()).reshape([batchsize, -1]);
+        let quantized = self.codebook.forward(z);
+
+        let z = quantized.quantized.reshape([batchsize, 1, -1]);
+        let y = y.reshape([batchsize, -1, 1]);
+        let output = self.decoder.forward(Tensor::cat(vec![z, y], 2));
+        let recon_loss =
+            MSELoss::new().forward(output, x, Reduction::Mean);
+
+        VQVAEOutput::new(
+            recon_loss,
+            quantized.commitment_loss.mul_scalar(self.commitment_weight),
+            quantized.perplexity,
+            quantized.usage,
+        )
+    }
+
+    pub(crate) fn _generate(
+        &self,
+        t: f32,
+        n: usize,
+        device: &B::Device,
+    ) -> Batches<B> {
+        let codes =
+            self.codebook.sample(n, device).reshape([n as i32, 1, -1]);
+        let t = Tensor::from_floats([t])
+            .to_device(device)
+            .unsqueeze::<3>()
+            .repeat(0, n);
+
+        self.decoder.forward(Tensor::cat(vec![codes, t], 2))
+    }
+
+    pub(crate) fn _encode(
+        &self,
+        x: Vec<Point>,
+    ) -> (Batches<B>, Batches<B>) {
+        let batchsize = x.len() as i32;
+        let z = self.encoder.forward(to_batch(x));
+        let quantized = self
+            .codebook
+            .quantize(z.clone().reshape([batchsize, -1]))
+            .reshape([batchsize, 1, -1]);
+
+        (z, quantized)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: AutodiffBackend> TrainStep<SpiralBatch<B>, VQVAEOutput<B>>
+    for VQVAE<B>
+{
+    fn step(&self, batch: SpiralBatch<B>) -> TrainOutput<VQVAEOutput<B>> {
+        let prediction = self.forward(batch.points, batch.labels);
+        let loss = prediction.recon_loss.clone()
+            + prediction.commitment_loss.clone();
+
+        TrainOutput::new(self, loss.backward(), prediction)
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+impl<B: Backend> ValidStep<SpiralBatch<B>, VQVAEOutput<B>> for VQVAE<B> {
+    fn step(&self, batch: SpiralBatch<B>) -> VQVAEOutput<B> {
+        self.forward(batch.points, batch.labels)
+    }
+}
@@ -38,8 +38,12 @@ pub fn App() -> impl IntoView {
     }
 }
 
+#[cfg(not(feature = "quantized"))]
 static MODEL_BYTES: &[u8] =
     include_bytes!("../../model_artifacts/model.bin");
+#[cfg(feature = "quantized")]
+static MODEL_BYTES: &[u8] =
+    include_bytes!("../../model_artifacts/model.q8.bin");
 
 #[component]
 fn Main() -> impl IntoView {
@@ -85,7 +89,10 @@ fn Plot(
     let r = create_rw_signal(6.);
     let (rendered, set_rendered) = create_signal(false);
 
+    #[cfg(not(feature = "quantized"))]
     inference::load_bytes(model_config, MODEL_BYTES.to_vec());
+    #[cfg(feature = "quantized")]
+    inference::load_quantized(model_config, MODEL_BYTES.to_vec());
     let generated =
         create_local_resource(|| (), move |_| generate(r() as f32));
 